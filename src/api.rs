@@ -7,7 +7,27 @@
 )]
 
 /*- Imports -*/
-use crate::{ utils, safe_user::SafeUser, tweet::Tweet };
+use crate::{ utils, safe_user::SafeUser, tweet::Tweet, config::CONFIG };
+use crate::password::{ hash_password, verify_password, verify_dummy_password, PasswordCheck };
+use crate::keys::KEYRING;
+use crate::tokens;
+use crate::sessions;
+use crate::api_keys::{ self, Scope };
+use crate::oauth::{ self, AuthorizationRequest, TokenError };
+use crate::oidc;
+use crate::audit::{ self, EventKind, Outcome, AuditQuery };
+use crate::moderation::{ self, AccountState };
+use crate::invites;
+use crate::exports::{ self, ExportStatus };
+use crate::verification;
+use crate::password_reset;
+use crate::magic_link;
+use crate::totp;
+use crate::throttle;
+use crate::policy::{ self, PolicyViolation };
+use crate::profile;
+use crate::deletion;
+use crate::roles::{ Role, Permission };
 use crate::dict::{ DICTIONARY, get_error_code };
 use fastserve::ResponseTypeImage;
 
@@ -25,7 +45,13 @@ use crate::user::{
     generate_uuid,
     generate_suid,
     authenticate,
+    authenticate_first_party,
     check_email,
+    get_user,
+    find_by_identifier,
+    normalize_identifier,
+    is_email_verified,
+    in_use_message,
 };
 use std::{
     io::{
@@ -70,7 +96,48 @@ pub(crate) const MONGO_CLIENT_URI_STRING:  &'static str = "mongodb://mongo:27017
     that lies somewhere in utils.rs -*/
 pub(crate) const REQUIRED_HEADERS: &'static [(&'static str, &[&'static str])] = &[
     ("create_account",  &["username", "displayname", "password", "email"]),
-    ("login",           &["email", "password"]),
+    ("login",           &["password"]),
+    ("refresh",         &["refresh_token"]),
+    ("logout",          &["Authorization"]),
+    ("sessions",        &["Authorization"]),
+    ("revoke_session",  &["Authorization", "sid"]),
+    ("revoke_other_sessions", &["Authorization"]),
+    ("api_keys",        &["Authorization"]),
+    ("create_api_key",  &["Authorization", "name", "scopes"]),
+    ("delete_api_key",  &["Authorization", "id"]),
+    ("oauth_clients",   &["Authorization"]),
+    ("oauth_register_client", &["Authorization", "name", "redirect_uris"]),
+    ("oauth_delete_client", &["Authorization", "client_id"]),
+    ("oauth_authorize", &["Authorization", "client_id", "redirect_uri", "scope", "code_challenge", "code_challenge_method"]),
+    ("oauth_consent",   &["Authorization", "client_id", "redirect_uri", "scope", "code_challenge", "code_challenge_method", "approve"]),
+    ("oauth_token",     &["grant_type", "client_id"]),
+    ("userinfo",        &["Authorization"]),
+    ("resend_verification", &["Authorization"]),
+    ("request_password_reset", &["email"]),
+    ("reset_password",  &["token", "password"]),
+    ("login_two_factor", &["challenge", "code"]),
+    ("request_magic_link", &["email"]),
+    ("exchange_magic_link", &["token"]),
+    ("two_factor_enroll", &["Authorization"]),
+    ("two_factor_confirm", &["Authorization", "code"]),
+    ("two_factor_disable", &["Authorization", "password", "code"]),
+    ("update_account",  &["Authorization"]),
+    ("update_profile",  &["Authorization"]),
+    ("delete_account",  &["Authorization", "password"]),
+    ("invites",         &["Authorization"]),
+    ("create_invite",   &["Authorization"]),
+    ("export_data",     &["Authorization"]),
+    ("export_status",   &["Authorization", "id"]),
+    ("deactivate_account", &["Authorization", "password"]),
+    ("admin_lockouts",  &["Authorization"]),
+    ("admin_lockout",   &["Authorization", "email"]),
+    ("admin_unlock",    &["Authorization", "key"]),
+    ("admin_grant_role", &["Authorization", "suid", "role"]),
+    ("admin_revoke_role", &["Authorization", "suid", "role"]),
+    ("admin_audit",     &["Authorization"]),
+    ("admin_suspend",   &["Authorization", "suid", "reason", "duration"]),
+    ("admin_ban",       &["Authorization", "suid", "reason"]),
+    ("admin_reinstate", &["Authorization", "suid", "reason"]),
     ("auth_test",       &["Authorization"]),
     ("tweet",           &["Authorization", "content"]),
     ("like",            &["Authorization", "tweet"]),
@@ -85,34 +152,56 @@ pub(super) fn create_account(
     
     /*- Require some headers to be specified -*/
     let required = utils::get_required_headers("create_account");
-    println!("required: {:?}", required);
-    println!("request: {}", request);
     let headers  = parse_headers(request, HeaderReturn::All);
-    println!("headers: {:?}", headers);
     if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
 
     /*- Initialize the user -*/
-    let user:User;
-
-    println!("Hej");
+    let mut user:User;
+    let invite_code:Option<String>;
 
     /*- Get the headers -*/
     if let HeaderReturn::Values(headers) = headers {
+        /*- Usernames and emails are stored normalized -*/
+        let username = normalize_identifier(headers.get("username").unwrap());
+        let email    = normalize_identifier(headers.get("email").unwrap());
+
+        /*- Check the username and password against the policy -*/
+        let policy_check = policy::check_username(&username)
+            .and_then(|_| policy::check_password(
+                headers.get("password").unwrap(),
+                &username,
+                &email
+            ));
+        if let Err(violation) = policy_check {
+            return respond(&mut stream, 400, Some((ResponseType::Text, &violation.message())), None);
+        };
+
+        /*- And the date of birth against the minimum age -*/
+        let date_of_birth = match policy::check_date_of_birth(headers.get("date_of_birth").map(|e| e.as_str())) {
+            Ok(date_of_birth) => date_of_birth,
+            Err(violation @ PolicyViolation::TooYoung(_)) =>
+                return respond(&mut stream, 403, Some((ResponseType::Text, &violation.message())), None),
+            Err(violation) => return respond(&mut stream, 400, Some((ResponseType::Text, &violation.message())), None),
+        };
+
         /*- Get the values -*/
         user = User {
-            username    : headers.get("username").unwrap().to_string(),
+            username,
             displayname : headers.get("displayname").unwrap().to_string(),
-            password    : utils::hash(headers.get("password").unwrap()),
-            email       : headers.get("email").unwrap().to_string(),
-            age         : 0,
+            password    : hash_password(headers.get("password").unwrap()),
+            email,
+            date_of_birth,
+            show_age    : headers.get("show_age").map(|e| e == "true").unwrap_or(false),
+            created     : Some(utils::get_unix_epoch_time()),
             uid         : generate_uuid(),
             suid        : generate_suid(),
+            ..User::default()
         };
+        invite_code = headers.get("invite").map(|e| e.to_string());
     }
     /*- If parsing headers was unsuccessful -*/
     else { return respond(&mut stream, 404, None, None); };
-    println!("{:?}", user);
-
 
     /*- If the email is invalid -*/
     if !check_email(&user.email) {
@@ -157,89 +246,2057 @@ pub(super) fn create_account(
             None
         );
     };
-    
-    /*- Insert the document -*/
-    collection.insert_one(user, None).ok();
+    
+    /*- Invite-only registration requires an invite code. One sent
+        otherwise is used too, so that it's known who invited whom -*/
+    match (&invite_code, CONFIG.invite_only) {
+        (Some(code), _) => match invites::redeem(code, &user.suid) {
+            Ok(invite) => user.invited_by = Some(invite.created_by),
+            Err(_) => return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.invalid.invite)), None),
+        },
+        (None, true)  => return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.invalid.invite)), None),
+        (None, false) => (),
+    };
+
+    /*- Insert the document. The unique indexes catch
+        accounts registered since the checks above -*/
+    if let Err(e) = collection.insert_one(user.clone(), None) {
+        if let Some(code) = &invite_code { invites::release(code, &user.suid); };
+        return match in_use_message(&e) {
+            Some(message) => respond(&mut stream, 409, Some((ResponseType::Text, message)), None),
+            None => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+        };
+    };
+
+    let detail = user.invited_by.as_ref().map(|suid| format!("invited by {}", suid));
+    audit::record(&stream, &user_agent, EventKind::AccountCreated, Outcome::Success, Some(&user.suid), detail.as_deref());
+
+    /*- The account stays pending until the email is verified. If the
+        mail can't be sent, the user can ask for a new one after logging in -*/
+    if let Err(e) = verification::send_verification_mail(&user) {
+        println!("Failed to send verification mail: {}", e);
+    };
+
+    /*- Respond with a success message -*/
+    respond(&mut stream, 200u16, None, None);
+}
+
+/*- Verify an email using the token mailed to it -*/
+pub(crate) fn verify_email(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- The token is in the URL-params, as it's opened from a link -*/
+    let token:&str = &params
+        .get("token")
+        .unwrap_or(
+            &"".to_string()
+        ).to_string();
+
+    /*- Check the token -*/
+    let claims = match verification::decode_verification_token(token) {
+        Ok(claims) => claims,
+        Err(_) => return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.verification)), None),
+    };
+
+    /*- Only verify if the email hasn't changed since the token was sent -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    match collection.update_one(
+        doc!{ "suid": &claims.suid, "email": &claims.email },
+        doc!{ "$set": { "email_verified": true } },
+        None
+    ) {
+        Ok(result) if result.matched_count > 0 => {
+            let user_agent = user_agent_of(&parse_headers(request, HeaderReturn::All));
+            audit::record(&stream, &user_agent, EventKind::EmailVerified, Outcome::Success, Some(&claims.suid), None);
+            respond(&mut stream, 200u16, Some((ResponseType::Text, DICTIONARY.success.email_verified)), None)
+        },
+        Ok(_) =>
+            respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.verification)), None),
+        Err(_) =>
+            respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Send a new verification mail -*/
+pub(crate) fn resend_verification(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("resend_verification");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Nothing to do if already verified -*/
+    if user.email_verified { return respond(&mut stream, 200u16, None, None); };
+
+    /*- Send the mail -*/
+    match verification::send_verification_mail(&user) {
+        Ok(_)  => respond(&mut stream, 200u16, None, None),
+        Err(e) => {
+            println!("Failed to send verification mail: {}", e);
+            respond(&mut stream, 500u16, Some((ResponseType::Text, DICTIONARY.error.mail)), None)
+        }
+    };
+}
+
+/*- Login accounts -*/
+pub(super) fn login(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("login");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Initialize the user -*/
+    let password:String;
+    let identifier:String;
+    let user_agent:String;
+
+    /*- Get the headers -*/
+    if let HeaderReturn::Values(headers) = headers {
+        /*- Get the values. Either the username or the email identifies the user -*/
+        password   = headers.get("password").unwrap().to_string();
+        identifier = match headers.get("identifier").or_else(|| headers.get("email")).or_else(|| headers.get("username")) {
+            Some(identifier) => identifier.to_string(),
+            None => return respond(&mut stream, 400u16, None, None),
+        };
+        user_agent = sessions::user_agent(&headers);
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Get the user. Throttling is per account, whichever identifier is used -*/
+    let user = find_by_identifier(&identifier);
+    let account_key = throttle::account_key(user.as_ref().map(|user| user.email.as_str()).unwrap_or(&identifier));
+    let ip_key      = throttle::ip_key(&stream);
+
+    /*- Refuse while the account or the client's IP is locked out -*/
+    if let Err(retry_after) = throttle::check(&[&account_key, &ip_key]) {
+        audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, user.as_ref().map(|user| user.suid.as_str()), Some("throttled"));
+        return respond_throttled(&mut stream, retry_after);
+    };
+
+    /*- An unknown user is treated exactly like a wrong
+        password, so that neither the response nor its
+        timing reveals if the username or email is in use -*/
+    let user:User = match user {
+        Some(user) => user,
+        None => {
+            verify_dummy_password(&password);
+
+            /*- Without the identifier, which may well be a password typed in the wrong field -*/
+            audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, None, Some("unknown user"));
+            return respond_login_failed(&mut stream, &account_key, &ip_key);
+        }
+    };
+
+    /*- Establish the mongodb connection -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+
+    /*- Check if password is correct -*/
+    match verify_password(&password, &user.password) {
+        PasswordCheck::Valid => (),
+
+        /*- Legacy SHA3 (or outdated) hashes get upgraded silently -*/
+        PasswordCheck::ValidNeedsRehash => {
+            collection.update_one(
+                doc!{ "suid": user.suid.clone() },
+                doc!{ "$set": { "password": hash_password(&password) } },
+                None
+            ).ok();
+        },
+        PasswordCheck::Invalid => {
+            audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, Some(&user.suid), Some("wrong password"));
+            return respond_login_failed(&mut stream, &account_key, &ip_key);
+        },
+    };
+
+    /*- The password was right. IP failures are kept, as an attacker
+        could otherwise reset them by logging in to their own account -*/
+    throttle::record_success(&account_key);
+
+    /*- Only now, so that the state isn't revealed without the password -*/
+    if refuse_inactive(&mut stream, &user_agent, &user) { return; };
+
+    /*- With 2FA enabled, the client gets a challenge to
+        exchange together with a code at /login/2fa instead -*/
+    if user.totp_enabled {
+        return respond_two_factor_required(&mut stream, &user.suid);
+    };
+
+    finish_login(&mut stream, &user_agent, user, None);
+}
+
+/*- Mail a login link, for logging in without a password -*/
+pub(super) fn request_magic_link(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("request_magic_link");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Get the email -*/
+    let email:String;
+    if let HeaderReturn::Values(headers) = headers {
+        email = headers.get("email").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Limit how many links are mailed to an address, and requested by
+        an IP. Whether the account exists or not, so that it isn't revealed -*/
+    let account_key = throttle::purpose_key(&throttle::account_key(&email), "magic-link");
+    let ip_key      = throttle::purpose_key(&throttle::ip_key(&stream), "magic-link");
+    if let Err(retry_after) = throttle::check(&[&account_key, &ip_key]) {
+        return respond_throttled(&mut stream, retry_after);
+    };
+    throttle::record_request(&account_key);
+    throttle::record_request(&ip_key);
+
+    /*- Send the mail if the account exists -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    if let Ok(Some(user)) = collection.find_one(doc!{ "email": normalize_identifier(&email) }, None) {
+        if let Err(e) = magic_link::send_magic_link(&user) {
+            println!("Failed to send login link: {}", e);
+        };
+    };
+
+    /*- Always respond the same, so that this
+        can't be used to find out if an email is in use -*/
+    respond(&mut stream, 200u16, None, None);
+}
+
+/*- Log in with the token from a login link. The response is the same as /login's -*/
+pub(super) fn exchange_magic_link(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("exchange_magic_link");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Get the token -*/
+    let token:String;
+    if let HeaderReturn::Values(headers) = headers {
+        token = headers.get("token").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Use up the token. It only works for the email it was sent to -*/
+    let user:User = match magic_link::consume_magic_link(&token)
+        .and_then(|claims| get_user(&claims.suid).filter(|user| user.email == claims.email).ok_or(())) {
+        Ok(user) => user,
+        Err(_) => {
+            audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, None, Some("invalid login link"));
+            return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.invalid.magic_link)), None);
+        },
+    };
+
+    if refuse_inactive(&mut stream, &user_agent, &user) { return; };
+
+    /*- The link only replaces the password, not the second factor -*/
+    if user.totp_enabled {
+        return respond_two_factor_required(&mut stream, &user.suid);
+    };
+
+    /*- The link was mailed, so following it proves the email belongs to the user -*/
+    if !user.email_verified {
+        let collection:Collection<User> = utils::establish_mclient::<User>("test");
+        collection.update_one(doc!{ "suid": &user.suid }, doc!{ "$set": { "email_verified": true } }, None).ok();
+    };
+
+    finish_login(&mut stream, &user_agent, user, Some("magic link"));
+}
+
+/*- Second login step for users with 2FA enabled -*/
+pub(super) fn login_two_factor(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("login_two_factor");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Get the values -*/
+    let challenge:String;
+    let code:String;
+    let user_agent:String;
+    if let HeaderReturn::Values(headers) = headers {
+        challenge  = headers.get("challenge").unwrap().to_string();
+        code       = headers.get("code").unwrap().to_string();
+        user_agent = sessions::user_agent(&headers);
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Check the challenge from the first step -*/
+    let claims = match totp::decode_challenge(&challenge) {
+        Ok(claims) => claims,
+        Err(_) => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Codes are throttled just like passwords -*/
+    let account_key = throttle::account_key(&user.email);
+    let ip_key      = throttle::ip_key(&stream);
+    if let Err(retry_after) = throttle::check(&[&account_key, &ip_key]) {
+        audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, Some(&user.suid), Some("throttled"));
+        return respond_throttled(&mut stream, retry_after);
+    };
+
+    /*- Check the TOTP or recovery code -*/
+    if !totp::consume_second_factor(&user, &code) {
+        throttle::record_failure(&account_key);
+        throttle::record_failure(&ip_key);
+        audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, Some(&user.suid), Some("wrong two-factor code"));
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.code)), None);
+    };
+    throttle::record_success(&account_key);
+
+    /*- A challenge can only be exchanged once -*/
+    if !totp::consume_challenge(&claims) {
+        audit::record(&stream, &user_agent, EventKind::Login, Outcome::Failure, Some(&user.suid), Some("reused two-factor challenge"));
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None);
+    };
+
+    /*- The account may have been suspended since the first step -*/
+    if refuse_inactive(&mut stream, &user_agent, &user) { return; };
+
+    finish_login(&mut stream, &user_agent, user, Some("two-factor"));
+}
+
+/*- Start enrolling in 2FA by generating a secret -*/
+pub(super) fn two_factor_enroll(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("two_factor_enroll");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+    if user.totp_enabled {
+        return respond(&mut stream, 409u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.enabled)), None);
+    };
+
+    /*- Store the secret, encrypted. It isn't used until confirmed -*/
+    let secret = totp::generate_secret();
+    let encrypted = match totp::encrypt_secret(&secret, &user.suid) {
+        Some(encrypted) => encrypted,
+        None => return respond(&mut stream, 503u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.unavailable)), None),
+    };
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    if collection.update_one(
+        doc!{ "suid": &user.suid },
+        doc!{ "$set": { "totp_secret": &encrypted } },
+        None
+    ).is_err() {
+        return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None);
+    };
+
+    /*- The URI can be shown as a QR code -*/
+    respond(
+        &mut stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &serde_json::json!({
+                "secret": secret,
+                "uri"   : totp::otpauth_uri(&secret, &user.email),
+            }).to_string()
+        )),
+        None
+    );
+}
+
+/*- Finish enrolling in 2FA with a code from the authenticator app -*/
+pub(super) fn two_factor_confirm(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("two_factor_confirm");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the code -*/
+    let code:String;
+    if let HeaderReturn::Values(headers) = headers {
+        code = headers.get("code").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Get the user, who must have started enrolling -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+    if user.totp_enabled {
+        return respond(&mut stream, 409u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.enabled)), None);
+    };
+    let secret = match user.totp_secret.as_ref().and_then(|secret| totp::decrypt_secret(secret, &user.suid)) {
+        Some(secret) => secret,
+        None => return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.not_enrolled)), None),
+    };
+
+    /*- Check the code -*/
+    let step = match totp::verify_code(&secret, &code, 0) {
+        Some(step) => step,
+        None => {
+            audit::record(&stream, &user_agent, EventKind::TwoFactorEnabled, Outcome::Failure, Some(&user.suid), Some("wrong two-factor code"));
+            return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.code)), None);
+        },
+    };
+
+    /*- Enable 2FA along with a fresh set of recovery codes -*/
+    let recovery_codes = totp::generate_recovery_codes();
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    if collection.update_one(
+        doc!{ "suid": &user.suid },
+        doc!{ "$set": {
+            "totp_enabled"   : true,
+            "totp_last_step" : step as i64,
+            "recovery_codes" : recovery_codes.iter().map(|code| utils::hash(code)).collect::<Vec<String>>(),
+        } },
+        None
+    ).is_err() {
+        return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None);
+    };
+
+    audit::record(&stream, &user_agent, EventKind::TwoFactorEnabled, Outcome::Success, Some(&user.suid), None);
+
+    /*- The recovery codes are only ever shown here -*/
+    respond(
+        &mut stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &serde_json::json!({ "recovery_codes": recovery_codes }).to_string()
+        )),
+        None
+    );
+}
+
+/*- Turn 2FA off, which requires both the password and a code -*/
+pub(super) fn two_factor_disable(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("two_factor_disable");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the values -*/
+    let password:String;
+    let code:String;
+    if let HeaderReturn::Values(headers) = headers {
+        password = headers.get("password").unwrap().to_string();
+        code     = headers.get("code").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Check both factors -*/
+    if verify_password(&password, &user.password) == PasswordCheck::Invalid {
+        audit::record(&stream, &user_agent, EventKind::TwoFactorDisabled, Outcome::Failure, Some(&user.suid), Some("wrong password"));
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.login)), None);
+    };
+    if !totp::consume_second_factor(&user, &code) {
+        audit::record(&stream, &user_agent, EventKind::TwoFactorDisabled, Outcome::Failure, Some(&user.suid), Some("wrong two-factor code"));
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.two_factor.code)), None);
+    };
+
+    /*- Remove everything 2FA related -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    match collection.update_one(
+        doc!{ "suid": &user.suid },
+        doc!{ "$set": {
+            "totp_secret"    : null,
+            "totp_enabled"   : false,
+            "totp_last_step" : 0i64,
+            "recovery_codes" : [],
+        } },
+        None
+    ) {
+        Ok(_)  => {
+            audit::record(&stream, &user_agent, EventKind::TwoFactorDisabled, Outcome::Success, Some(&user.suid), None);
+            respond(&mut stream, 200u16, None, None)
+        },
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Exchange a refresh token for a new access token -*/
+pub(super) fn refresh(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("refresh");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Get the refresh token -*/
+    let refresh_token:String;
+    if let HeaderReturn::Values(headers) = headers {
+        refresh_token = headers.get("refresh_token").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- The family is the session, which must exist and not have been revoked.
+        Third-party apps refresh at /oauth/token, keeping their scopes. This is
+        checked before rotating, so that a token sent here by mistake isn't used up -*/
+    match tokens::refresh_token_family(&refresh_token).and_then(|sid| sessions::get(&sid)) {
+        Some(session) if session.client_id.is_none() && !session.revoked => (),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Rotate the refresh token. It can't be used again after this -*/
+    let (refresh_token, suid, sid) = match tokens::rotate_refresh_token(&refresh_token) {
+        Ok(rotated) => rotated,
+        Err(_) => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- In case it was revoked in the meantime -*/
+    if !sessions::check_and_touch(&sid) {
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None);
+    };
+
+    /*- Get the user, as the access token embeds their username -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let user:User = match collection.find_one(doc!{ "suid": &suid }, None) {
+        Ok(Some(user)) => user,
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Respond with the new token pair -*/
+    respond(
+        &mut stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &token_response(user, &sid, &refresh_token)
+        )),
+        None
+    );
+}
+
+/*- End the current session, revoking its access and refresh tokens -*/
+pub(super) fn logout(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("logout");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Revoke the session, which takes its refresh tokens with it -*/
+    tokens::revoke_access_token(&user_claims);
+    sessions::revoke(&user_claims.suid, &user_claims.sid);
+
+    /*- Tokens from before sessions existed name their refresh token instead -*/
+    if let HeaderReturn::Values(headers) = headers {
+        if let Some(refresh_token) = headers.get("refresh_token") {
+            tokens::revoke_refresh_token(refresh_token);
+        };
+    };
+
+    audit::record(&stream, &user_agent, EventKind::Logout, Outcome::Success, Some(&user_claims.suid), None);
+
+    /*- Respond with a success message -*/
+    respond(&mut stream, 200u16, None, None);
+}
+
+/*- List the user's active sessions -*/
+pub(super) fn list_sessions(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("sessions");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Mark the session making the request -*/
+    let sessions = sessions::list(&user_claims.suid).into_iter().map(|session| serde_json::json!({
+        "sid"        : session.sid,
+        "user_agent" : session.user_agent,
+        "ip"         : session.ip,
+        "created"    : session.created,
+        "last_seen"  : session.last_seen,
+        "client_id"  : session.client_id,
+        "current"    : session.sid == user_claims.sid,
+    })).collect::<Vec<serde_json::Value>>();
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::Value::Array(sessions).to_string())),
+        None
+    );
+}
+
+/*- Log out one of the user's sessions -*/
+pub(super) fn revoke_session(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("revoke_session");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the session id -*/
+    let sid:String;
+    if let HeaderReturn::Values(headers) = headers {
+        sid = headers.get("sid").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Users can only revoke their own sessions -*/
+    match sessions::revoke(&user_claims.suid, &sid) {
+        true  => {
+            audit::record(&stream, &user_agent, EventKind::SessionRevoked, Outcome::Success, Some(&user_claims.suid), Some(&sid));
+            respond(&mut stream, 200u16, None, None)
+        },
+        false => respond(&mut stream, 404u16, None, None),
+    };
+}
+
+/*- Log out every session except the one making the request -*/
+pub(super) fn revoke_other_sessions(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("revoke_other_sessions");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    sessions::revoke_others(&user_claims.suid, &user_claims.sid);
+    audit::record(&stream, &user_agent, EventKind::SessionRevoked, Outcome::Success, Some(&user_claims.suid), Some("all other sessions"));
+    respond(&mut stream, 200u16, None, None);
+}
+
+/*- List the user's API keys. The keys themselves are never shown again -*/
+pub(super) fn list_api_keys(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("api_keys");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    let api_keys = api_keys::list(&user_claims.suid).into_iter().map(|api_key| serde_json::json!({
+        "id"        : api_key.id,
+        "name"      : api_key.name,
+        "prefix"    : api_key.prefix,
+        "scopes"    : api_key.scopes,
+        "created"   : api_key.created,
+        "expires"   : api_key.expires,
+        "last_used" : api_key.last_used,
+    })).collect::<Vec<serde_json::Value>>();
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::Value::Array(api_keys).to_string())),
+        None
+    );
+}
+
+/*- Create an API key. The optional "expires_in" header
+    is the number of seconds the key is valid for -*/
+pub(super) fn create_api_key(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("create_api_key");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability. Keys can't create more keys -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the values -*/
+    let name:String;
+    let scopes:String;
+    let expires_in:Option<String>;
+    if let HeaderReturn::Values(headers) = headers {
+        name       = headers.get("name").unwrap().to_string();
+        scopes     = headers.get("scopes").unwrap().to_string();
+        expires_in = headers.get("expires_in").map(|e| e.to_string());
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Scopes are comma separated, like "tweet:write,like:write" -*/
+    let scopes = match api_keys::parse_scopes(&scopes) {
+        Ok(scopes) => scopes,
+        Err(_) => return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.scope)), None),
+    };
+
+    /*- Keys without an expiry are valid until deleted -*/
+    let expires = match expires_in.map(|e| e.parse::<u64>()) {
+        Some(Ok(expires_in)) => Some(utils::get_unix_epoch_time() + expires_in),
+        Some(Err(_))         => return respond(&mut stream, 400u16, None, None),
+        None                 => None,
+    };
+
+    match api_keys::create_api_key(&user_claims.suid, &name, scopes, expires) {
+        /*- The only time the key is shown -*/
+        Ok((api_key, key)) => {
+            audit::record(&stream, &user_agent, EventKind::ApiKeyCreated, Outcome::Success, Some(&user_claims.suid), Some(&api_key.id));
+            respond(
+                &mut stream,
+                200u16,
+                Some((
+                    ResponseType::Json,
+                    &serde_json::json!({
+                        "id"      : api_key.id,
+                        "key"     : key,
+                        "scopes"  : api_key.scopes,
+                        "expires" : api_key.expires,
+                    }).to_string()
+                )),
+                None
+            )
+        },
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Delete one of the user's API keys -*/
+pub(super) fn delete_api_key(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("delete_api_key");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the key id -*/
+    let id:String;
+    if let HeaderReturn::Values(headers) = headers {
+        id = headers.get("id").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    match api_keys::delete_api_key(&user_claims.suid, &id) {
+        true  => {
+            audit::record(&stream, &user_agent, EventKind::ApiKeyDeleted, Outcome::Success, Some(&user_claims.suid), Some(&id));
+            respond(&mut stream, 200u16, None, None)
+        },
+        false => respond(&mut stream, 404u16, None, None),
+    };
+}
+
+/*- List the third-party apps the user has registered -*/
+pub(super) fn oauth_clients(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("oauth_clients");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    let clients = oauth::list_clients(&user_claims.suid).into_iter().map(|client| serde_json::json!({
+        "client_id"     : client.client_id,
+        "name"          : client.name,
+        "redirect_uris" : client.redirect_uris,
+        "confidential"  : client.secret_hash.is_some(),
+        "created"       : client.created,
+    })).collect::<Vec<serde_json::Value>>();
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::Value::Array(clients).to_string())),
+        None
+    );
+}
+
+/*- Register a third-party app. Redirect URIs are comma separated,
+    and "confidential: true" gives the app a client secret -*/
+pub(super) fn oauth_register_client(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("oauth_register_client");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the values -*/
+    let name:String;
+    let redirect_uris:Vec<String>;
+    let confidential:bool;
+    if let HeaderReturn::Values(headers) = headers {
+        name          = headers.get("name").unwrap().to_string();
+        redirect_uris = headers.get("redirect_uris").unwrap()
+            .split(',')
+            .map(|uri| uri.trim().to_string())
+            .filter(|uri| !uri.is_empty())
+            .collect();
+        confidential  = headers.get("confidential").map(|e| e == "true").unwrap_or(false);
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- See oauth::check_redirect_uri for which are allowed -*/
+    if redirect_uris.is_empty() || !redirect_uris.iter().all(|uri| oauth::check_redirect_uri(uri)) {
+        return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.redirect_uri)), None);
+    };
+
+    match oauth::register_client(&user_claims.suid, &name, redirect_uris, confidential) {
+        /*- The only time the secret is shown -*/
+        Ok((client, secret)) => respond(
+            &mut stream,
+            200u16,
+            Some((
+                ResponseType::Json,
+                &serde_json::json!({
+                    "client_id"     : client.client_id,
+                    "client_secret" : secret,
+                }).to_string()
+            )),
+            None
+        ),
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Delete one of the user's third-party apps -*/
+pub(super) fn oauth_delete_client(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("oauth_delete_client");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the client id -*/
+    let client_id:String;
+    if let HeaderReturn::Values(headers) = headers {
+        client_id = headers.get("client_id").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    match oauth::delete_client(&user_claims.suid, &client_id) {
+        true  => respond(&mut stream, 200u16, None, None),
+        false => respond(&mut stream, 404u16, None, None),
+    };
+}
+
+/*- Check an authorization request, responding with what the
+    consent screen should show: the app and the scopes it wants -*/
+pub(super) fn oauth_authorize(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("oauth_authorize");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    match authenticate_first_party(&stream, headers.clone()) {
+        Ok(_) => (),
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Check the request -*/
+    let authorization_request = match headers {
+        HeaderReturn::Values(headers) => AuthorizationRequest::parse(&headers),
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let authorization_request = match authorization_request {
+        Ok(authorization_request) => authorization_request,
+        Err(message) => return respond(&mut stream, 400u16, Some((ResponseType::Text, message)), None),
+    };
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &serde_json::json!({
+                "client_id" : authorization_request.client.client_id,
+                "name"      : authorization_request.client.name,
+                "scopes"    : authorization_request.scopes,
+            }).to_string()
+        )),
+        None
+    );
+}
+
+/*- The user approves or denies an authorization request. Responds
+    with where to redirect them, with either a code or an error -*/
+pub(super) fn oauth_consent(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("oauth_consent");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Check the request again, it may have been tampered with since -*/
+    let (authorization_request, approve) = match headers {
+        HeaderReturn::Values(headers) => (
+            AuthorizationRequest::parse(&headers),
+            headers.get("approve").map(|e| e == "true").unwrap_or(false)
+        ),
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let authorization_request = match authorization_request {
+        Ok(authorization_request) => authorization_request,
+        Err(message) => return respond(&mut stream, 400u16, Some((ResponseType::Text, message)), None),
+    };
+
+    /*- Denied -*/
+    let redirect = if !approve {
+        authorization_request.redirect("error=access_denied")
+    }else {
+        match oauth::create_code(&authorization_request, &user_claims.suid) {
+            Ok(code) => authorization_request.redirect(&format!("code={}", code)),
+            Err(_) => return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+        }
+    };
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::json!({ "redirect": redirect }).to_string())),
+        None
+    );
+}
+
+/*- Where apps exchange authorization codes and refresh
+    tokens for tokens. "grant_type" is either
+    "authorization_code" or "refresh_token" -*/
+pub(super) fn oauth_token(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("oauth_token");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    let headers = match headers {
+        HeaderReturn::Values(headers) => headers,
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let header = |name:&str| headers.get(name).map(|e| e.to_string()).unwrap_or_default();
+
+    /*- Confidential apps must authenticate -*/
+    let client = match oauth::authenticate_client(&header("client_id"), headers.get("client_secret").map(|e| e.as_str())) {
+        Ok(client) => client,
+        Err(error) => return respond_oauth_error(&mut stream, error),
+    };
+
+    let (suid, sid, scopes, refresh_token, nonce) = match header("grant_type").as_str() {
+        /*- The user has just consented, start a session for the app -*/
+        "authorization_code" => {
+            let code = header("code");
+            let authorization_code = match oauth::exchange_code(&code, &client.client_id, &header("redirect_uri"), &header("code_verifier")) {
+                Ok(authorization_code) => authorization_code,
+                Err(error) => return respond_oauth_error(&mut stream, error),
+            };
+
+            let sid = sessions::start_client_session(
+                &authorization_code.suid,
+                &client.name,
+                &utils::client_ip(&stream),
+                &client.client_id,
+                authorization_code.scopes.clone()
+            );
+            oauth::bind_code(&code, &sid);
+
+            let refresh_token = tokens::issue_refresh_token(&authorization_code.suid, Some(&sid));
+            (authorization_code.suid, sid, authorization_code.scopes, refresh_token, authorization_code.nonce)
+        },
+
+        /*- Continue a session the app already has -*/
+        "refresh_token" => {
+            /*- The session must be the app's own, and still active. This is checked
+                before rotating, so that another client can't use the token up -*/
+            let scopes = match tokens::refresh_token_family(&header("refresh_token")).and_then(|sid| sessions::get(&sid)) {
+                Some(session) if session.client_id.as_deref() == Some(client.client_id.as_str()) && !session.revoked =>
+                    session.scopes.unwrap_or_default(),
+                _ => return respond_oauth_error(&mut stream, TokenError::InvalidGrant),
+            };
+
+            let (refresh_token, suid, sid) = match tokens::rotate_refresh_token(&header("refresh_token")) {
+                Ok(rotated) => rotated,
+                Err(_) => return respond_oauth_error(&mut stream, TokenError::InvalidGrant),
+            };
+            if !sessions::check_and_touch(&sid) {
+                return respond_oauth_error(&mut stream, TokenError::InvalidGrant);
+            };
+
+            (suid, sid, scopes, refresh_token, None)
+        },
+        "" => return respond_oauth_error(&mut stream, TokenError::InvalidRequest),
+        _  => return respond_oauth_error(&mut stream, TokenError::UnsupportedGrantType),
+    };
+
+    /*- Get the user, as the access token embeds their username -*/
+    let user:User = match get_user(&suid) {
+        Some(user) => user,
+        None => return respond_oauth_error(&mut stream, TokenError::InvalidGrant),
+    };
+
+    let mut response = serde_json::json!({
+        "access_token"  : User::create__scoped__JWT__token(user.clone(), &sid, Some(scopes.clone())),
+        "token_type"    : "Bearer",
+        "expires_in"    : CONFIG.access_token_lifetime,
+        "refresh_token" : refresh_token,
+        "scope"         : scopes.iter().map(|scope| scope.name()).collect::<Vec<&str>>().join(" "),
+    });
+
+    /*- OpenID Connect apps also get to know who signed in -*/
+    if scopes.contains(&Scope::OpenId) {
+        if let Some(id_token) = oidc::create_id_token(&user, &client.client_id, &scopes, nonce) {
+            response["id_token"] = serde_json::json!(id_token);
+        };
+    };
+
+    respond(&mut stream, 200u16, Some((ResponseType::Json, &response.to_string())), None);
+}
+
+/*- The claims about the user which the token's scopes allow -*/
+pub(super) fn userinfo(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("userinfo");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate(&stream, headers) {
+        AuthorizationStatus::Authorized(v) => v,
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Apps need the openid scope -*/
+    if !user_claims.has_scope(Scope::OpenId) {
+        return respond_missing_scope(&mut stream, Scope::OpenId);
+    };
+
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Tokens from logging in may see everything -*/
+    let scopes = user_claims.scopes.unwrap_or_else(|| vec![Scope::OpenId, Scope::Profile, Scope::Email]);
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::to_string(&oidc::user_info(&user, &scopes)).unwrap())),
+        None
+    );
+}
+
+/*- Tell OpenID Connect apps where everything is -*/
+pub(crate) fn openid_configuration(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Not found without a key to sign ID tokens with -*/
+    match oidc::discovery_document() {
+        Some(document) => respond(&mut stream, 200u16, Some((ResponseType::Json, &document.to_string())), None),
+        None => respond(&mut stream, 404u16, None, None),
+    };
+}
+
+/*- Respond with an error the way OAuth clients expect it -*/
+fn respond_oauth_error(stream:&mut TcpStream, error:TokenError) -> () {
+    respond(
+        stream,
+        error.status(),
+        Some((ResponseType::Json, &serde_json::json!({ "error": error.code() }).to_string())),
+        None
+    )
+}
+
+/*- Count a failed login and respond with the uniform error -*/
+fn respond_login_failed(stream:&mut TcpStream, account_key:&str, ip_key:&str) -> () {
+    throttle::record_failure(account_key);
+    throttle::record_failure(ip_key);
+
+    respond(stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.login)), None)
+}
+
+/*- Respond that the client has to wait before trying again -*/
+fn respond_throttled(stream:&mut TcpStream, retry_after:u64) -> () {
+    respond(
+        stream,
+        429u16,
+        Some((
+            ResponseType::Text,
+            &DICTIONARY.error.throttled.replace("{}", &retry_after.to_string())
+        )),
+        None
+    )
+}
+
+/*- Respond that authenticating for a first-party endpoint failed -*/
+fn respond_unauthenticated(stream:&mut TcpStream, status:u16) -> () {
+    let message = match status {
+        403 => DICTIONARY.error.delegated,
+        _   => DICTIONARY.error.unauthorized,
+    };
+    respond(stream, status, Some((ResponseType::Text, message)), None)
+}
+
+/*- Respond that the API key used lacks a scope -*/
+fn respond_missing_scope(stream:&mut TcpStream, scope:Scope) -> () {
+    respond(
+        stream,
+        403u16,
+        Some((
+            ResponseType::Text,
+            &DICTIONARY.error.scope.replace("{}", scope.name())
+        )),
+        None
+    )
+}
+
+/*- With 2FA enabled, the client gets a challenge to
+    exchange together with a code at /login/2fa -*/
+fn respond_two_factor_required(stream:&mut TcpStream, suid:&str) -> () {
+    let challenge = match totp::create_challenge(suid) {
+        Ok(challenge) => challenge,
+        Err(_) => return respond(stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+
+    respond(
+        stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &format!(
+                "{}\"two_factor_required\":true,\"challenge\":\"{}\"{}",
+                "{", challenge, "}"
+            )
+        )),
+        None
+    )
+}
+
+/*- Start a session for a user who has proven who they are, and respond with its tokens -*/
+fn finish_login(stream:&mut TcpStream, user_agent:&str, user:User, detail:Option<&str>) -> () {
+    /*- Logging in during the grace period calls off a deletion -*/
+    if user.deletion_scheduled.is_some() {
+        deletion::cancel_deletion(&user.suid);
+    };
+
+    /*- And so does it reactivate a deactivated account -*/
+    if user.account_state == AccountState::Deactivated {
+        reactivate(stream, user_agent, &user.suid);
+    };
+
+    /*- Record the login as a new session -*/
+    audit::record(stream, user_agent, EventKind::Login, Outcome::Success, Some(&user.suid), detail);
+    let (sid, refresh_token) = start_session(stream, user_agent, &user.suid);
+
+    /*- Respond with a success message -*/
+    respond(
+        stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &token_response(user, &sid, &refresh_token)
+        )),
+        None
+    );
+}
+
+/*- Refuse logging in to suspended and banned accounts. Returns true if refused -*/
+fn refuse_inactive(stream:&mut TcpStream, user_agent:&str, user:&User) -> bool {
+    let message = match user.account_state.current() {
+        AccountState::Banned              => DICTIONARY.error.banned.to_string(),
+        AccountState::Suspended { until } => DICTIONARY.error.suspended.replace("{}", &until.to_string()),
+        _ => return false,
+    };
+
+    audit::record(stream, user_agent, EventKind::Login, Outcome::Failure, Some(&user.suid), Some("inactive account"));
+    respond(stream, 403u16, Some((ResponseType::Text, &message)), None);
+    true
+}
+
+/*- Reactivate an account its user deactivated -*/
+fn reactivate(stream:&TcpStream, user_agent:&str, suid:&str) -> () {
+    if moderation::set_state(suid, AccountState::Active, None).is_ok() {
+        audit::record(stream, user_agent, EventKind::AccountReinstated, Outcome::Success, Some(suid), Some("logged in"));
+    };
+}
+
+/*- The User-Agent of a request, for sessions and the audit log -*/
+fn user_agent_of(headers:&HeaderReturn) -> String {
+    match headers {
+        HeaderReturn::Values(headers) => sessions::user_agent(headers),
+        _ => "unknown".to_string(),
+    }
+}
+
+/*- Record a new session for the client, starting its refresh
+    token family. Returns the session id and the refresh token -*/
+fn start_session(stream:&TcpStream, user_agent:&str, suid:&str) -> (String, String) {
+    let sid = sessions::start_session(suid, user_agent, &utils::client_ip(stream));
+    let refresh_token = tokens::issue_refresh_token(suid, Some(&sid));
+
+    (sid, refresh_token)
+}
+
+/*- The JSON which endpoints handing out tokens respond with -*/
+fn token_response(user:User, sid:&str, refresh_token:&str) -> String {
+    /*- Create the access token -*/
+    let token = User::create__JWT__token(user.clone(), sid);
+
+    /*- Format some JSON -*/
+    format!(
+        "{}\"token\":\"{}\",\"refresh_token\":\"{}\",\"suid\":\"{}\"{}",
+        "{", &token, refresh_token, &user.suid, "}"
+    )
+}
+
+/*- Change the displayname, username, email and / or password.
+    Changing the email or password requires the current password -*/
+pub(super) fn update_account(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("update_account");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Every field is optional -*/
+    let headers = match headers {
+        HeaderReturn::Values(headers) => headers,
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let displayname      = headers.get("displayname").map(|e| e.to_string());
+    let username         = headers.get("username").map(|e| normalize_identifier(e));
+    let email            = headers.get("email").map(|e| normalize_identifier(e));
+    let password         = headers.get("password").map(|e| e.to_string());
+    let current_password = headers.get("current_password").map(|e| e.to_string());
+    let date_of_birth    = headers.get("date_of_birth").map(|e| e.to_string());
+    let show_age         = headers.get("show_age").map(|e| e == "true");
+    let user_agent       = sessions::user_agent(&headers);
+
+    /*- Get the user -*/
+    let mut user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let mut changes = Document::new();
+
+    /*- Sensitive changes need the current password -*/
+    let email_changed = email.as_ref().map(|email| email != &user.email).unwrap_or(false);
+    if email_changed || password.is_some() {
+        let current_password = current_password.unwrap_or_default();
+        if verify_password(&current_password, &user.password) == PasswordCheck::Invalid {
+            audit::record(&stream, &user_agent, EventKind::AccountUpdated, Outcome::Failure, Some(&user.suid), Some("wrong password"));
+            return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.login)), None);
+        };
+    };
+
+    /*- Displayname -*/
+    if let Some(displayname) = displayname {
+        changes.insert("displayname", &displayname);
+        user.displayname = displayname;
+    };
+
+    /*- Date of birth, which must meet the minimum age too. An
+        empty one removes it, unless there is a minimum age -*/
+    if let Some(date_of_birth) = date_of_birth {
+        let date_of_birth = match policy::check_date_of_birth(Some(&date_of_birth)) {
+            Ok(date_of_birth) => date_of_birth,
+            Err(violation @ PolicyViolation::TooYoung(_)) =>
+                return respond(&mut stream, 403u16, Some((ResponseType::Text, &violation.message())), None),
+            Err(violation) => return respond(&mut stream, 400u16, Some((ResponseType::Text, &violation.message())), None),
+        };
+
+        changes.insert("date_of_birth", date_of_birth.map(|date_of_birth| date_of_birth.to_string()));
+        user.date_of_birth = date_of_birth;
+    };
+
+    /*- If the age is shown on the profile -*/
+    if let Some(show_age) = show_age {
+        changes.insert("show_age", show_age);
+        user.show_age = show_age;
+    };
+
+    /*- Username, which must follow the policy and be unique -*/
+    if let Some(username) = username.filter(|username| username != &user.username) {
+        if let Err(violation) = policy::check_username(&username) {
+            return respond(&mut stream, 400u16, Some((ResponseType::Text, &violation.message())), None);
+        };
+        if collection.find_one(doc!{ "username": &username }, None).ok().flatten().is_some() {
+            return respond(&mut stream, 409u16, Some((ResponseType::Text, DICTIONARY.error.in_use.username)), None);
+        };
+
+        changes.insert("username", &username);
+        user.username = username;
+    };
+
+    /*- Email, which needs to be verified again -*/
+    if let Some(email) = email.filter(|_| email_changed) {
+        if !check_email(&email) {
+            return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.email)), None);
+        };
+        if collection.find_one(doc!{ "email": &email }, None).ok().flatten().is_some() {
+            return respond(&mut stream, 409u16, Some((ResponseType::Text, DICTIONARY.error.in_use.email)), None);
+        };
+
+        changes.insert("email", &email);
+        changes.insert("email_verified", false);
+        user.email = email;
+        user.email_verified = false;
+    };
+
+    /*- Password -*/
+    if let Some(password) = &password {
+        if let Err(violation) = policy::check_password(password, &user.username, &user.email) {
+            return respond(&mut stream, 400u16, Some((ResponseType::Text, &violation.message())), None);
+        };
+
+        changes.insert("password", hash_password(password));
+    };
+
+    /*- Save. The unique indexes catch usernames
+        and emails taken since the checks above -*/
+    if !changes.is_empty() {
+        let changed = changes.keys().filter(|key| *key != "email_verified").cloned().collect::<Vec<String>>().join(",");
+        if let Err(e) = collection.update_one(
+            doc!{ "suid": &user.suid },
+            doc!{ "$set": changes },
+            None
+        ) {
+            return match in_use_message(&e) {
+                Some(message) => respond(&mut stream, 409u16, Some((ResponseType::Text, message)), None),
+                None => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+            };
+        };
+        audit::record(&stream, &user_agent, EventKind::AccountUpdated, Outcome::Success, Some(&user.suid), Some(&changed));
+    };
+
+    /*- Send the verification mail to the new address -*/
+    if email_changed {
+        if let Err(e) = verification::send_verification_mail(&user) {
+            println!("Failed to send verification mail: {}", e);
+        };
+    };
+
+    /*- A new password logs out every other session, so
+        the client gets a new session of its own -*/
+    if password.is_some() {
+        tokens::revoke_all_for_user(&user.suid);
+        let (sid, refresh_token) = start_session(&stream, &user_agent, &user.suid);
+
+        return respond(&mut stream, 200u16, Some((ResponseType::Json, &token_response(user, &sid, &refresh_token))), None);
+    };
+
+    /*- The access token embeds the username, so re-issue it -*/
+    respond(
+        &mut stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &serde_json::json!({
+                "token": User::create__JWT__token(user.clone(), &user_claims.sid),
+                "suid" : user.suid,
+            }).to_string()
+        )),
+        None
+    );
+}
+
+/*- Change the public profile: bio, location, website, pronouns and banner -*/
+pub(super) fn update_profile(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("update_profile");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Every field is optional, and an empty one is removed -*/
+    let headers = match headers {
+        HeaderReturn::Values(headers) => headers,
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let user_agent = sessions::user_agent(&headers);
+    let mut changes = Document::new();
+
+    /*- Check every field before changing anything -*/
+    let checks:[(&str, fn(&str) -> Result<Option<String>, PolicyViolation>); 4] = [
+        ("bio",      profile::check_bio),
+        ("location", profile::check_location),
+        ("website",  profile::check_website),
+        ("pronouns", profile::check_pronouns),
+    ];
+    for (field, check) in checks {
+        if let Some(value) = headers.get(field) {
+            match check(value) {
+                Ok(value) => { changes.insert(field, value); },
+                Err(violation) => return respond(&mut stream, 400u16, Some((ResponseType::Text, &violation.message())), None),
+            };
+        };
+    };
+
+    /*- The banner, a base64 encoded image -*/
+    let banner = match headers.get("banner").map(|e| e.trim()) {
+        Some("") => Some(None),
+        Some(encoded) => match profile::decode_banner(encoded) {
+            Ok(banner) => Some(Some(banner)),
+            Err(violation) => return respond(&mut stream, 400u16, Some((ResponseType::Text, &violation.message())), None),
+        },
+        None => None,
+    };
+    if let Some(banner) = &banner { changes.insert("banner", banner.is_some()); };
+    let had_banner = get_user(&user_claims.suid).map(|user| user.banner).unwrap_or(false);
+
+    /*- Save. The banner file is only changed once the document has been -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    if !changes.is_empty() {
+        let changed = changes.keys().cloned().collect::<Vec<String>>().join(",");
+        if collection.update_one(
+            doc!{ "suid": &user_claims.suid },
+            doc!{ "$set": changes },
+            None
+        ).is_err() {
+            return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None);
+        };
+
+        /*- If the new banner can't be written, the flag is put back. A
+            banner which was there before is only replaced once it has been -*/
+        match &banner {
+            Some(Some(banner)) => if let Err(e) = profile::save_banner(&user_claims.suid, banner) {
+                println!("Failed to save banner: {}", e);
+                collection.update_one(doc!{ "suid": &user_claims.suid }, doc!{ "$set": { "banner": had_banner } }, None).ok();
+                return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(102))), None);
+            },
+            Some(None) => profile::delete_banner(&user_claims.suid),
+            None => (),
+        };
+        audit::record(&stream, &user_agent, EventKind::ProfileUpdated, Outcome::Success, Some(&user_claims.suid), Some(&changed));
+    };
+
+    /*- Respond with the profile as others see it -*/
+    match get_user(&user_claims.suid) {
+        Some(user) => respond(&mut stream, 200u16, Some((ResponseType::Json, &serde_json::to_string(&User::to_safe(user)).unwrap())), None),
+        None => respond(&mut stream, 404u16, None, None),
+    };
+}
+
+/*- List the invite codes the user has created, and who used them -*/
+pub(super) fn list_invites(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("invites");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::to_string(&invites::list(&user_claims.suid)).unwrap())),
+        None
+    );
+}
+
+/*- Create an invite code. Admins may send "max_uses", and
+    anyone "expires_in", the seconds the code is valid for -*/
+pub(super) fn create_invite(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("create_invite");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the values -*/
+    let headers = match headers {
+        HeaderReturn::Values(headers) => headers,
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let number = |name:&str| match headers.get(name) {
+        Some(value) => value.parse::<u64>().map(Some),
+        None        => Ok(None),
+    };
+    let (max_uses, expires_in) = match (number("max_uses"), number("expires_in")) {
+        (Ok(max_uses), Ok(expires_in)) => (max_uses, expires_in),
+        _ => return respond(&mut stream, 400u16, None, None),
+    };
+
+    /*- Users get a number of single-use codes, admins as many as they like -*/
+    let max_uses = match user_claims.has_permission(Permission::CreateInvites) {
+        true  => max_uses.unwrap_or(1).max(1),
+        false if max_uses.unwrap_or(1) != 1 =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        false if invites::count(&user_claims.suid) >= CONFIG.invite_quota =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.invite_quota)), None),
+        false => 1,
+    };
+
+    let expires = expires_in.map(|expires_in| utils::get_unix_epoch_time() + expires_in);
+    match invites::create_invite(&user_claims.suid, max_uses, expires) {
+        Ok(invite) => {
+            audit::record(&stream, &user_agent, EventKind::InviteCreated, Outcome::Success, Some(&user_claims.suid), Some(&invite.code));
+            respond(&mut stream, 200u16, Some((ResponseType::Json, &serde_json::to_string(&invite).unwrap())), None)
+        },
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Start building an archive of everything stored about the user. It's
+    mailed once ready, and can be downloaded from the link in the response -*/
+pub(super) fn export_data(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("export_data");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- One at a time -*/
+    if exports::is_pending(&user.suid) {
+        return respond(&mut stream, 409u16, Some((ResponseType::Text, DICTIONARY.error.export_pending)), None);
+    };
+
+    match exports::request_export(&user) {
+        Ok((export, token)) => {
+            audit::record(&stream, &user_agent, EventKind::DataExported, Outcome::Success, Some(&user.suid), Some(&export.id));
+            respond(
+                &mut stream,
+                200u16,
+                Some((
+                    ResponseType::Json,
+                    &serde_json::json!({
+                        "id"       : export.id,
+                        "status"   : export.status,
+                        "expires"  : export.expires,
+                        "download" : exports::download_link(&token),
+                    }).to_string()
+                )),
+                None
+            )
+        },
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Check if an export is ready -*/
+pub(super) fn export_status(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("export_status");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the export id -*/
+    let id:String;
+    if let HeaderReturn::Values(headers) = headers {
+        id = headers.get("id").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    match exports::get_export(&user_claims.suid, &id) {
+        Some(export) => respond(
+            &mut stream,
+            200u16,
+            Some((
+                ResponseType::Json,
+                &serde_json::json!({
+                    "id"      : export.id,
+                    "status"  : export.status,
+                    "created" : export.created,
+                    "expires" : export.expires,
+                }).to_string()
+            )),
+            None
+        ),
+        None => respond(&mut stream, 404u16, None, None),
+    };
+}
+
+/*- Download an export. The token in the URL is all that's needed,
+    so that the link can be opened straight from the mail -*/
+pub(crate) fn download_export(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    let token:&str = &params
+        .get("token")
+        .unwrap_or(
+            &"".to_string()
+        ).to_string();
+
+    /*- Not built yet, failed, or unknown -*/
+    let archive = match exports::read_archive(token) {
+        Ok(archive) => archive,
+        Err(Some(ExportStatus::Pending)) => return respond(&mut stream, 202u16, None, None),
+        Err(Some(_)) => return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+        Err(None)    => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Encode -*/
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = Encoder::with_chunks_size(&mut encoded, 64);
+        encoder.write_all(&archive).unwrap_or_default();
+    }
+
+    /*- Create the response -*/
+    let headers = [
+        "HTTP/1.1 200 OK",
+        "Content-type: application/zip",
+        "Content-Disposition: attachment; filename=\"export.zip\"",
+        "Transfer-Encoding: chunked",
+        "\r\n"
+    ];
+    let mut response = headers.join("\r\n")
+        .to_string()
+        .into_bytes();
+        response.extend(encoded);
+
+    /*- Respond with the archive -*/
+    stream.write(&response).unwrap_or_default();
+}
+
+/*- Delete the account and everything belonging to it -*/
+pub(super) fn delete_account(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("delete_account");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the password -*/
+    let password:String;
+    if let HeaderReturn::Values(headers) = headers {
+        password = headers.get("password").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Confirm with the password -*/
+    if verify_password(&password, &user.password) == PasswordCheck::Invalid {
+        audit::record(&stream, &user_agent, EventKind::AccountDeleted, Outcome::Failure, Some(&user.suid), Some("wrong password"));
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.login)), None);
+    };
+
+    /*- Delete, or schedule the deletion -*/
+    match deletion::delete_account(&user) {
+        Ok(_)  => {
+            let detail = match CONFIG.account_deletion_grace { 0 => None, _ => Some("scheduled") };
+            audit::record(&stream, &user_agent, EventKind::AccountDeleted, Outcome::Success, Some(&user.suid), detail);
+            respond(&mut stream, 200u16, None, None)
+        },
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Deactivate the account, hiding it until the user logs in again -*/
+pub(super) fn deactivate_account(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("deactivate_account");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Check the auth availability -*/
+    let user_claims:UserClaims = match authenticate_first_party(&stream, headers.clone()) {
+        Ok(v) => v,
+        Err(status) => return respond_unauthenticated(&mut stream, status),
+    };
+
+    /*- Get the password -*/
+    let password:String;
+    if let HeaderReturn::Values(headers) = headers {
+        password = headers.get("password").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Get the user -*/
+    let user:User = match get_user(&user_claims.suid) {
+        Some(user) => user,
+        None => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Confirm with the password -*/
+    if verify_password(&password, &user.password) == PasswordCheck::Invalid {
+        audit::record(&stream, &user_agent, EventKind::AccountDeactivated, Outcome::Failure, Some(&user.suid), Some("wrong password"));
+        return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.login)), None);
+    };
+
+    /*- Deactivating logs out everywhere -*/
+    match moderation::set_state(&user.suid, AccountState::Deactivated, None) {
+        Ok(_)  => {
+            audit::record(&stream, &user_agent, EventKind::AccountDeactivated, Outcome::Success, Some(&user.suid), None);
+            respond(&mut stream, 200u16, None, None)
+        },
+        Err(_) => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- Mail a password reset link -*/
+pub(super) fn request_password_reset(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("request_password_reset");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Get the email -*/
+    let email:String;
+    if let HeaderReturn::Values(headers) = headers {
+        email = headers.get("email").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Limit how many reset mails are sent to an address, and requested by an IP.
+        Throttled requests get the same response, they just don't send anything -*/
+    let account_key = throttle::purpose_key(&throttle::account_key(&email), "password-reset");
+    let ip_key      = throttle::purpose_key(&throttle::ip_key(&stream), "password-reset");
+    if throttle::check(&[&account_key, &ip_key]).is_err() {
+        return respond(&mut stream, 200u16, None, None);
+    };
+    throttle::record_request(&account_key);
+    throttle::record_request(&ip_key);
 
-    /*- Respond with a success message -*/
+    /*- Send the mail if the account exists -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    if let Ok(Some(user)) = collection.find_one(doc!{ "email": normalize_identifier(&email) }, None) {
+        if let Err(e) = password_reset::send_password_reset(&user) {
+            println!("Failed to send password reset mail: {}", e);
+        };
+    };
+
+    /*- Always respond the same, so that this
+        can't be used to find out if an email is in use -*/
     respond(&mut stream, 200u16, None, None);
 }
 
-/*- Login accounts -*/
-pub(super) fn login(
+/*- Set a new password using a reset token -*/
+pub(super) fn reset_password(
     mut stream : TcpStream,
         request: String,
         params : HashMap<String, String>
 ) -> () {
     /*- Require some headers to be specified -*/
-    let required = utils::get_required_headers("login");
+    let required = utils::get_required_headers("reset_password");
     let headers  = parse_headers(request, HeaderReturn::All);
     if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
 
-    /*- Initialize the user -*/
+    /*- Get the values -*/
+    let token:String;
     let password:String;
-    let email:String;
-
-    /*- Get the headers -*/
     if let HeaderReturn::Values(headers) = headers {
-        /*- Get the values -*/
+        token    = headers.get("token").unwrap().to_string();
         password = headers.get("password").unwrap().to_string();
-        email    = headers.get("email").unwrap().to_string();
     }
     /*- If parsing headers was unsuccessful -*/
     else { return respond(&mut stream, 404, None, None); };
 
-    /*- Establish the mongodb connection -*/
-    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    /*- Look the token up without using it, so that a
+        rejected password doesn't waste the reset link -*/
+    let user:User = match password_reset::find_password_reset(&token).and_then(|suid| get_user(&suid).ok_or(())) {
+        Ok(user) => user,
+        Err(_) => {
+            audit::record(&stream, &user_agent, EventKind::PasswordReset, Outcome::Failure, None, Some("invalid reset token"));
+            return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.password_reset)), None);
+        },
+    };
 
-    /*- Check if email exists -*/
-    let email_exists = collection.find(doc!{"email": email.to_string()}, None).unwrap().next().is_some();
-    if !email_exists {
-        return respond(
-            &mut stream,
-            404,
-            Some((
-                ResponseType::Text,
-                DICTIONARY.error.login
-            )),
-            None
-        );
+    /*- Check the new password against the policy -*/
+    if let Err(violation) = policy::check_password(&password, &user.username, &user.email) {
+        return respond(&mut stream, 400u16, Some((ResponseType::Text, &violation.message())), None);
     };
 
-    /*- Get the user -*/
-    let user = collection.find(doc!{"email": email.to_string()}, None).unwrap().next().unwrap().unwrap();
+    /*- Use up the token -*/
+    let suid = match password_reset::consume_password_reset(&token) {
+        Ok(suid) => suid,
+        Err(_) => return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.password_reset)), None),
+    };
 
-    /*- Check if password is correct -*/
-    if &user.password != &utils::hash(&password) {
-        return respond(
-            &mut stream,
-            401,
-            Some((
-                ResponseType::Text,
-                DICTIONARY.error.login
-            )),
-            None
-        );
+    /*- Set the new password. The reset link was mailed, so
+        following it also proves the email belongs to the user -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    if collection.update_one(
+        doc!{ "suid": &suid },
+        doc!{ "$set": { "password": hash_password(&password), "email_verified": true } },
+        None
+    ).is_err() {
+        return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None);
     };
 
-    /*- Create the token -*/
-    let token = User::create__JWT__token(user.clone());
+    /*- Whoever else might know the old password gets logged out. API keys
+        can't be told apart from ones they made, so they all go -*/
+    tokens::revoke_all_for_user(&suid);
+    api_keys::delete_api_keys(&suid);
+    audit::record(&stream, &user_agent, EventKind::PasswordReset, Outcome::Success, Some(&suid), None);
 
     /*- Respond with a success message -*/
-    respond(
-        &mut stream,
-        200u16,
-        Some((
-            ResponseType::Json,
-    
-            /*- Format some JSON -*/
-            &format!(
-                "{}\"token\":\"{}\",\"suid\":\"{}\"{}",
-                "{", &token, &user.suid, "}"
-            )
-        )),
-        None
-    );
+    respond(&mut stream, 200u16, None, None);
 }
 
 /*- Get other user's profile -*/
@@ -271,6 +2328,9 @@ pub(crate) fn profile_data(
     let user_data:SafeUser = User::to_safe(match user_exists {
         Ok(mut async_cursor) => {
             match async_cursor.next() {
+                /*- Accounts about to be deleted, suspended, banned or deactivated are hidden -*/
+                Some(Ok(user_data)) if user_data.deletion_scheduled.is_some() || !user_data.account_state.is_active() =>
+                    return respond(&mut stream, 404, None, None),
                 Some(user_data) => match user_data {
                     Ok(user_data) => user_data,
                     Err(_) => return respond(&mut stream, 404, None, None)
@@ -351,6 +2411,53 @@ pub(crate) fn profile_image(
     stream.write(&response).unwrap_or_default();
 }
 
+/*- Get a users banner image. Unlike profile
+    images, there's no default one to fall back to -*/
+pub(crate) fn profile_banner(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    let suid:&str = &params
+        .get("suid")
+        .unwrap_or(
+            &"".to_string()
+        ).to_string();
+
+    /*- Banners of hidden accounts aren't shown either -*/
+    match get_user(suid) {
+        Some(user) if user.banner && user.deletion_scheduled.is_none() && user.account_state.is_active() => (),
+        _ => return respond(&mut stream, 404u16, None, None),
+    };
+
+    let buf = match std::fs::read(profile::banner_path(suid)) {
+        Ok(buf) => buf,
+        Err(_) => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Encode -*/
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = Encoder::with_chunks_size(&mut encoded, 64);
+        encoder.write_all(&buf).unwrap_or_default();
+    }
+
+    /*- Create the response -*/
+    let headers = [
+        "HTTP/1.1 200 OK",
+        "Content-type: image/jpeg",
+        "Transfer-Encoding: chunked",
+        "\r\n"
+    ];
+    let mut response = headers.join("\r\n")
+        .to_string()
+        .into_bytes();
+        response.extend(encoded);
+
+    /*- Respond with the image -*/
+    stream.write(&response).unwrap_or_default();
+}
+
 /*- Get a feed -*/
 pub(crate) fn feed(
     mut stream : TcpStream,
@@ -361,8 +2468,8 @@ pub(crate) fn feed(
     /*- Establish the mongodb connection -*/
     let collection:Collection<Tweet> = utils::establish_mclient::<Tweet>("tweets");
     
-    /*- Get all -*/
-    let mut all_tweets = match collection.find(None, None) {
+    /*- Get all, except those of suspended, banned and deactivated users -*/
+    let mut all_tweets = match collection.find(doc!{ "owner": { "$nin": moderation::hidden_suids() } }, None) {
         Ok(tweets) => tweets,
         _ => return respond(&mut stream, 404u16, None, None),
     }.into_iter()
@@ -402,14 +2509,10 @@ pub(crate) fn tweet(
     /*- Require some headers to be specified -*/
     let required = utils::get_required_headers("tweet");
     let headers  = parse_headers(request.clone(), HeaderReturn::All);
-
-    println!("{:?}", required);
-    println!("{:?}", headers);
-
     if !expect_headers(&mut stream, &headers, required) { return; };
 
     /*- Check the auth availability -*/
-    let authentication_status:AuthorizationStatus = authenticate(headers.clone());
+    let authentication_status:AuthorizationStatus = authenticate(&stream, headers.clone());
     let user_claims:UserClaims = match authentication_status {
         AuthorizationStatus::Authorized(v) => v,
         AuthorizationStatus::Unauthorized => 
@@ -418,6 +2521,16 @@ pub(crate) fn tweet(
             return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None)
     };
 
+    /*- API keys need the tweet:write scope -*/
+    if !user_claims.has_scope(Scope::TweetWrite) {
+        return respond_missing_scope(&mut stream, Scope::TweetWrite);
+    };
+
+    /*- Pending accounts can't tweet -*/
+    if !is_email_verified(&user_claims.suid) {
+        return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.unverified)), None);
+    };
+
     /*- Get the "content" header -*/
     let content:String;
 
@@ -480,14 +2593,11 @@ pub(crate) fn like(
 
     /*- Require some headers to be specified -*/
     let required = utils::get_required_headers("like");
-    println!("{:?}", required);
-    println!("{:?}", request);
     let headers  = parse_headers(request.clone(), HeaderReturn::All);
-    println!("{:?}", headers);
     if !expect_headers(&mut stream, &headers, required) { return; };
 
     /*- Check the auth availability -*/
-    let authentication_status:AuthorizationStatus = authenticate(headers.clone());
+    let authentication_status:AuthorizationStatus = authenticate(&stream, headers.clone());
     let user_claims:UserClaims = match authentication_status {
         AuthorizationStatus::Authorized(v) => v,
         AuthorizationStatus::Unauthorized => 
@@ -496,6 +2606,16 @@ pub(crate) fn like(
             return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None)
     };
 
+    /*- API keys need the like:write scope -*/
+    if !user_claims.has_scope(Scope::LikeWrite) {
+        return respond_missing_scope(&mut stream, Scope::LikeWrite);
+    };
+
+    /*- Pending accounts can't like -*/
+    if !is_email_verified(&user_claims.suid) {
+        return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.unverified)), None);
+    };
+
     /*- Get the "tweet_id" header -*/
     let tweet_id:String;
 
@@ -567,7 +2687,7 @@ pub(crate) fn hashtag(
     if !expect_headers(&mut stream, &headers, required) { return; };
 
     /*- Check the auth availability -*/
-    let authentication_status:AuthorizationStatus = authenticate(headers.clone());
+    let authentication_status:AuthorizationStatus = authenticate(&stream, headers.clone());
 
     /*- Get the "hashtag" header -*/
     let hashtag:String;
@@ -584,8 +2704,8 @@ pub(crate) fn hashtag(
     /*- Establish the mongodb connection -*/
     let collection:Collection<Tweet> = utils::establish_mclient::<Tweet>("tweets");
 
-    /*- Get the tweets -*/
-    let tweets:Vec<Tweet> = match collection.find(Some(doc!{"hashtags": hashtag.clone()}), None) {
+    /*- Get the tweets, except those of suspended, banned and deactivated users -*/
+    let tweets:Vec<Tweet> = match collection.find(Some(doc!{"hashtags": hashtag.clone(), "owner": { "$nin": moderation::hidden_suids() }}), None) {
         Ok(tweets) => tweets,
         _ => return respond(&mut stream, 404u16, None, None),
     }.into_iter()
@@ -599,4 +2719,331 @@ pub(crate) fn hashtag(
         Some((ResponseType::Json, &serde_json::to_string(&tweets).unwrap_or("{}".to_string()))),
         None
     );
+}
+
+/*- Publish the public keys our tokens can be verified with -*/
+pub(crate) fn jwks(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::to_string(&KEYRING.jwk_set()).unwrap())),
+        None
+    );
+}
+
+/*- (Admin) List every account and IP which is currently locked out -*/
+pub(crate) fn admin_lockouts(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("admin_lockouts");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Only admins -*/
+    match authenticate(&stream, headers) {
+        AuthorizationStatus::Authorized(claims) if claims.has_permission(Permission::ManageLockouts) => (),
+        AuthorizationStatus::Authorized(_) =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((ResponseType::Json, &serde_json::to_string(&throttle::locked()).unwrap())),
+        None
+    );
+}
+
+/*- (Admin) Get the failed login attempts of an account -*/
+pub(crate) fn admin_lockout(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("admin_lockout");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Only admins -*/
+    match authenticate(&stream, headers.clone()) {
+        AuthorizationStatus::Authorized(claims) if claims.has_permission(Permission::ManageLockouts) => (),
+        AuthorizationStatus::Authorized(_) =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Get the email -*/
+    let email:String;
+    if let HeaderReturn::Values(headers) = headers {
+        email = headers.get("email").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Report the state, even when there are no failures -*/
+    let key      = throttle::account_key(&email);
+    let attempts = throttle::get(&key);
+    let now      = utils::get_unix_epoch_time();
+
+    respond(
+        &mut stream,
+        200u16,
+        Some((
+            ResponseType::Json,
+            &serde_json::json!({
+                "key"          : key,
+                "failures"     : attempts.as_ref().map(|e| e.failures).unwrap_or(0),
+                "last_failure" : attempts.as_ref().map(|e| e.last_failure),
+                "locked"       : attempts.as_ref().map(|e| e.locked_until > now).unwrap_or(false),
+                "locked_until" : attempts.as_ref().map(|e| e.locked_until).filter(|until| *until > now),
+            }).to_string()
+        )),
+        None
+    );
+}
+
+/*- (Admin) Lift the lockout of an account or IP -*/
+pub(crate) fn admin_unlock(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("admin_unlock");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Only admins -*/
+    match authenticate(&stream, headers.clone()) {
+        AuthorizationStatus::Authorized(claims) if claims.has_permission(Permission::ManageLockouts) => (),
+        AuthorizationStatus::Authorized(_) =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Get the key, as listed by admin/lockouts -*/
+    let key:String;
+    if let HeaderReturn::Values(headers) = headers {
+        key = headers.get("key").unwrap().to_string();
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    throttle::record_success(&key);
+    respond(&mut stream, 200u16, None, None);
+}
+
+/*- (Admin) Grant a role to a user -*/
+pub(crate) fn admin_grant_role(
+        stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    change_role(stream, request, "admin_grant_role", true)
+}
+
+/*- (Admin) Revoke a role from a user -*/
+pub(crate) fn admin_revoke_role(
+        stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    change_role(stream, request, "admin_revoke_role", false)
+}
+
+/*- (Admin) Query the audit log. Every header but the
+    token is optional: "suid", "event", "from" and "to"
+    (unix timestamps, inclusive) and "limit" -*/
+pub(crate) fn admin_audit(
+    mut stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers("admin_audit");
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+
+    /*- Only admins -*/
+    match authenticate(&stream, headers.clone()) {
+        AuthorizationStatus::Authorized(claims) if claims.has_permission(Permission::ViewAuditLog) => (),
+        AuthorizationStatus::Authorized(_) =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Get the filters -*/
+    let headers = match headers {
+        HeaderReturn::Values(headers) => headers,
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let number = |name:&str| match headers.get(name) {
+        Some(value) => value.parse::<u64>().map(Some),
+        None        => Ok(None),
+    };
+    let (from, to, limit) = match (number("from"), number("to"), number("limit")) {
+        (Ok(from), Ok(to), Ok(limit)) => (from, to, limit),
+        _ => return respond(&mut stream, 400u16, None, None),
+    };
+
+    /*- Events are named like in the log, e.g. "login" -*/
+    let kind = match headers.get("event").map(|e| serde_json::from_value::<EventKind>(serde_json::Value::String(e.to_string()))) {
+        Some(Ok(kind)) => Some(kind),
+        Some(Err(_))   => return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.event)), None),
+        None           => None,
+    };
+
+    let query = AuditQuery {
+        suid  : headers.get("suid").map(|e| e.to_string()),
+        kind,
+        from,
+        to,
+        limit : limit.map(|limit| limit as i64),
+    };
+
+    match audit::query(&query) {
+        Ok(events) => respond(&mut stream, 200u16, Some((ResponseType::Json, &serde_json::to_string(&events).unwrap())), None),
+        Err(_)     => respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+}
+
+/*- (Moderator) Suspend a user for "duration" seconds -*/
+pub(crate) fn admin_suspend(
+        stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    change_account_state(stream, request, "admin_suspend", EventKind::AccountSuspended)
+}
+
+/*- (Admin) Ban a user until they're reinstated -*/
+pub(crate) fn admin_ban(
+        stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    change_account_state(stream, request, "admin_ban", EventKind::AccountBanned)
+}
+
+/*- (Moderator) Lift a suspension, or (Admin) a ban -*/
+pub(crate) fn admin_reinstate(
+        stream : TcpStream,
+        request: String,
+        params : HashMap<String, String>
+) -> () {
+    change_account_state(stream, request, "admin_reinstate", EventKind::AccountReinstated)
+}
+
+/*- Suspend, ban or reinstate the user in the "suid" header. The
+    "reason" is kept on the user, and in the audit log -*/
+fn change_account_state(mut stream:TcpStream, request:String, endpoint:&'static str, kind:EventKind) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers(endpoint);
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Only admins and moderators -*/
+    let moderator = match authenticate(&stream, headers.clone()) {
+        AuthorizationStatus::Authorized(claims) if claims.has_permission(Permission::ModerateAccounts) => claims,
+        AuthorizationStatus::Authorized(_) =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Get the values -*/
+    let headers = match headers {
+        HeaderReturn::Values(headers) => headers,
+        _ => return respond(&mut stream, 404, None, None),
+    };
+    let suid   = headers.get("suid").unwrap().to_string();
+    let reason = headers.get("reason").unwrap().to_string();
+    let state  = match kind {
+        EventKind::AccountSuspended => match headers.get("duration").map(|e| e.parse::<u64>()) {
+            Some(Ok(duration)) if duration > 0 => AccountState::Suspended { until: utils::get_unix_epoch_time() + duration },
+            _ => return respond(&mut stream, 400u16, None, None),
+        },
+        EventKind::AccountBanned => AccountState::Banned,
+        _ => AccountState::Active,
+    };
+
+    /*- Moderators may only suspend, see moderation::required_permission -*/
+    match get_user(&suid) {
+        Some(user) if moderator.has_permission(moderation::required_permission(&user, state)) => (),
+        Some(_) => return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        None    => return respond(&mut stream, 404u16, None, None),
+    };
+
+    /*- Suspending and banning also logs the user out -*/
+    match moderation::set_state(&suid, state, Some(&reason)) {
+        Ok(true)  => (),
+        Ok(false) => return respond(&mut stream, 404u16, None, None),
+        Err(_)    => return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+
+    audit::record(&stream, &user_agent, kind, Outcome::Success, Some(&suid), Some(&format!("{} (by {})", reason, moderator.suid)));
+    respond(&mut stream, 200u16, None, None);
+}
+
+/*- Grant or revoke the role in the "role" header -*/
+fn change_role(mut stream:TcpStream, request:String, endpoint:&'static str, grant:bool) -> () {
+    /*- Require some headers to be specified -*/
+    let required = utils::get_required_headers(endpoint);
+    let headers  = parse_headers(request, HeaderReturn::All);
+    if !expect_headers(&mut stream, &headers, required) { return; };
+    let user_agent = user_agent_of(&headers);
+
+    /*- Only admins -*/
+    let admin = match authenticate(&stream, headers.clone()) {
+        AuthorizationStatus::Authorized(claims) if claims.has_permission(Permission::ManageRoles) => claims,
+        AuthorizationStatus::Authorized(_) =>
+            return respond(&mut stream, 403u16, Some((ResponseType::Text, DICTIONARY.error.forbidden)), None),
+        _ => return respond(&mut stream, 401u16, Some((ResponseType::Text, DICTIONARY.error.unauthorized)), None),
+    };
+
+    /*- Get the values -*/
+    let suid:String;
+    let role:Role;
+    if let HeaderReturn::Values(headers) = headers {
+        suid = headers.get("suid").unwrap().to_string();
+        role = match headers.get("role").unwrap().parse::<Role>() {
+            /*- Everyone has the User role -*/
+            Ok(Role::User) | Err(_) => return respond(&mut stream, 400u16, Some((ResponseType::Text, DICTIONARY.error.invalid.role)), None),
+            Ok(role) => role,
+        };
+    }
+    /*- If parsing headers was unsuccessful -*/
+    else { return respond(&mut stream, 404, None, None); };
+
+    /*- Update the user's roles -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let role_name = serde_json::to_value(role).unwrap().as_str().unwrap().to_string();
+    let update = match grant {
+        true  => doc!{ "$addToSet": { "roles": &role_name } },
+        false => doc!{ "$pull": { "roles": &role_name } },
+    };
+    match collection.update_one(doc!{ "suid": &suid }, update, None) {
+        Ok(result) if result.matched_count == 0 => return respond(&mut stream, 404u16, None, None),
+        Ok(_) => (),
+        Err(_) => return respond(&mut stream, 500u16, Some((ResponseType::Text, &get_error_code(103))), None),
+    };
+
+    /*- Roles are carried in tokens, so a revoked role would otherwise
+        stay usable until they expire. Log the user out instead -*/
+    if !grant { tokens::revoke_all_for_user(&suid); };
+
+    /*- The event belongs to the user whose roles changed -*/
+    let kind = match grant { true => EventKind::RoleGranted, false => EventKind::RoleRevoked };
+    audit::record(&stream, &user_agent, kind, Outcome::Success, Some(&suid), Some(&format!("{} by {}", role_name, admin.suid)));
+
+    respond(&mut stream, 200u16, None, None);
 }
\ No newline at end of file
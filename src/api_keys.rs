@@ -0,0 +1,171 @@
+/*- Personal access tokens (API keys) -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, user::generate_suid };
+use serde::{ Serialize, Deserialize };
+use std::str::FromStr;
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const API_KEY_COLLECTION:&str = "api_keys";
+
+/*- Every key starts with this, which is how
+    authenticate tells them apart from JWTs -*/
+pub(crate) const API_KEY_PREFIX:&str = "ak_";
+
+/*- How much of a key is kept in plain text, so
+    that users can tell their keys apart -*/
+const DISPLAYED_LENGTH:usize = 10;
+
+/*- Structs, enums, unions -*/
+/// # Scope
+/// What an API key or third-party app may be used for. Tokens from
+/// logging in have every scope, API keys only the ones they were
+/// created with and apps only the ones the user consented to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Scope {
+    #[serde(rename = "tweet:write")]
+    TweetWrite,
+    #[serde(rename = "like:write")]
+    LikeWrite,
+
+    /*- OpenID Connect scopes, which decide
+        what apps get to know about the user -*/
+    #[serde(rename = "openid")]
+    OpenId,
+    #[serde(rename = "profile")]
+    Profile,
+    #[serde(rename = "email")]
+    Email,
+}
+
+/// # ApiKey
+/// A named, long-lived key a user has created for scripts and
+/// bots. Like refresh tokens, only the hash of the key is stored,
+/// so the plain key is only shown once, when it's created.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ApiKey {
+    pub id        : String,
+    pub key_hash  : String,
+    pub prefix    : String,
+    pub suid      : String,
+    pub name      : String,
+    pub scopes    : Vec<Scope>,
+    pub created   : u64,
+    pub expires   : Option<u64>,
+    pub last_used : Option<u64>,
+}
+
+/*- Function implementations -*/
+impl Scope {
+    /*- How the scope is written in headers and responses -*/
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::TweetWrite => "tweet:write",
+            Scope::LikeWrite  => "like:write",
+            Scope::OpenId     => "openid",
+            Scope::Profile    => "profile",
+            Scope::Email      => "email",
+        }
+    }
+}
+
+/*- Parse scopes sent in headers -*/
+impl FromStr for Scope {
+    type Err = ();
+    fn from_str(value:&str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "tweet:write" => Ok(Scope::TweetWrite),
+            "like:write"  => Ok(Scope::LikeWrite),
+            "openid"      => Ok(Scope::OpenId),
+            "profile"     => Ok(Scope::Profile),
+            "email"       => Ok(Scope::Email),
+            _             => Err(()),
+        }
+    }
+}
+
+/*- Functions -*/
+/*- Parse a comma or space separated list of scopes -*/
+pub(crate) fn parse_scopes(value:&str) -> Result<Vec<Scope>, ()> {
+    let mut scopes:Vec<Scope> = vec![];
+    for scope in value.split(|c:char| c == ',' || c.is_whitespace()).filter(|scope| !scope.is_empty()) {
+        let scope = Scope::from_str(scope)?;
+        if !scopes.contains(&scope) { scopes.push(scope); };
+    };
+
+    Ok(scopes)
+}
+
+/*- Create a key, returning it and the plain key to show the user -*/
+pub(crate) fn create_api_key(suid:&str, name:&str, scopes:Vec<Scope>, expires:Option<u64>) -> Result<(ApiKey, String), ()> {
+    let key = format!("{}{}", API_KEY_PREFIX, utils::generate_token());
+
+    let api_key = ApiKey {
+        id        : generate_suid(),
+        key_hash  : utils::hash(&key),
+        prefix    : key[..DISPLAYED_LENGTH].to_string(),
+        suid      : suid.to_string(),
+        name      : name.to_string(),
+        scopes,
+        created   : utils::get_unix_epoch_time(),
+        expires,
+        last_used : None,
+    };
+
+    let collection:Collection<ApiKey> = utils::establish_mclient::<ApiKey>(API_KEY_COLLECTION);
+    collection.insert_one(&api_key, None).map_err(|_| ())?;
+
+    Ok((api_key, key))
+}
+
+/*- Look up a (plain) key, returning it if it's valid -*/
+pub(crate) fn find_api_key(key:&str) -> Option<ApiKey> {
+    let collection:Collection<ApiKey> = utils::establish_mclient::<ApiKey>(API_KEY_COLLECTION);
+    let now = utils::get_unix_epoch_time();
+
+    let api_key = collection.find_one(doc!{ "key_hash": utils::hash(key) }, None).ok().flatten()?;
+    if api_key.expires.map(|expires| expires < now).unwrap_or(false) { return None; };
+
+    /*- Remember when it was last used -*/
+    collection.update_one(
+        doc!{ "id": &api_key.id },
+        doc!{ "$set": { "last_used": now as i64 } },
+        None
+    ).ok();
+
+    Some(api_key)
+}
+
+/*- Every key of a user -*/
+pub(crate) fn list(suid:&str) -> Vec<ApiKey> {
+    let collection:Collection<ApiKey> = utils::establish_mclient::<ApiKey>(API_KEY_COLLECTION);
+    match collection.find(doc!{ "suid": suid }, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect(),
+        Err(_)     => vec![],
+    }
+}
+
+/*- Delete one of a user's keys. Returns false if they have no such key -*/
+pub(crate) fn delete_api_key(suid:&str, id:&str) -> bool {
+    let collection:Collection<ApiKey> = utils::establish_mclient::<ApiKey>(API_KEY_COLLECTION);
+    match collection.delete_one(doc!{ "suid": suid, "id": id }, None) {
+        Ok(result) => result.deleted_count > 0,
+        Err(_)     => false,
+    }
+}
+
+/*- Delete every key of a user -*/
+pub(crate) fn delete_api_keys(suid:&str) -> () {
+    let collection:Collection<ApiKey> = utils::establish_mclient::<ApiKey>(API_KEY_COLLECTION);
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
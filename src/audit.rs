@@ -0,0 +1,175 @@
+/*- Security audit log -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::utils;
+use serde::{ Serialize, Deserialize };
+use std::net::TcpStream;
+use mongodb::{
+    bson::{ doc, Document },
+    sync::Collection,
+    options::FindOptions,
+};
+
+/*- Statics & Constants -*/
+const AUDIT_COLLECTION:&str = "audit_events";
+
+/*- The ip of events which didn't come from a request -*/
+const LOCAL_IP:&str = "local";
+
+/*- How many events a query returns at most -*/
+pub(crate) const MAX_QUERY_LIMIT:i64 = 1000;
+
+/*- Structs, enums, unions -*/
+/// # EventKind
+/// What happened.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EventKind {
+    AccountCreated,
+    Login,
+    Logout,
+    AuthenticationFailed,
+    AccountUpdated,
+    EmailVerified,
+    PasswordReset,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    AccountDeleted,
+    SessionRevoked,
+    ApiKeyCreated,
+    ApiKeyDeleted,
+    RoleGranted,
+    RoleRevoked,
+    AccountSuspended,
+    AccountBanned,
+    AccountReinstated,
+    AccountDeactivated,
+    InviteCreated,
+    DataExported,
+    ProfileUpdated,
+}
+
+/// # Outcome
+/// If what was attempted succeeded.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Outcome {
+    Success,
+    Failure,
+}
+
+/// # AuditEvent
+/// Something security related which happened to an account. Events
+/// are only ever inserted, never changed or removed, and are kept
+/// even after the account they belong to has been deleted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct AuditEvent {
+    pub kind       : EventKind,
+    pub outcome    : Outcome,
+    pub suid       : Option<String>,
+    pub ip         : String,
+    pub user_agent : String,
+    pub timestamp  : u64,
+    pub detail     : Option<String>,
+}
+
+/// # AuditQuery
+/// Which events to look for. Every field is optional.
+#[derive(Default, Debug)]
+pub(crate) struct AuditQuery {
+    pub suid  : Option<String>,
+    pub kind  : Option<EventKind>,
+    pub from  : Option<u64>,
+    pub to    : Option<u64>,
+    pub limit : Option<i64>,
+}
+
+/*- Functions -*/
+/*- Append an event to the log. A failure to write
+    it is logged, but doesn't fail the request -*/
+pub(crate) fn record(
+    stream     : &TcpStream,
+    user_agent : &str,
+    kind       : EventKind,
+    outcome    : Outcome,
+    suid       : Option<&str>,
+    detail     : Option<&str>
+) -> () {
+    insert(&utils::client_ip(stream), user_agent, kind, outcome, suid, detail)
+}
+
+/*- Append an event which didn't come from a request, like
+    the import command. The ip is "local" for these -*/
+pub(crate) fn record_local(
+    source     : &str,
+    kind       : EventKind,
+    outcome    : Outcome,
+    suid       : Option<&str>,
+    detail     : Option<&str>
+) -> () {
+    insert(LOCAL_IP, source, kind, outcome, suid, detail)
+}
+
+fn insert(
+    ip         : &str,
+    user_agent : &str,
+    kind       : EventKind,
+    outcome    : Outcome,
+    suid       : Option<&str>,
+    detail     : Option<&str>
+) -> () {
+    let collection:Collection<AuditEvent> = utils::establish_mclient::<AuditEvent>(AUDIT_COLLECTION);
+    if let Err(e) = collection.insert_one(AuditEvent {
+        kind,
+        outcome,
+        suid       : suid.map(|e| e.to_string()),
+        ip         : ip.to_string(),
+        user_agent : user_agent.to_string(),
+        timestamp  : utils::get_unix_epoch_time(),
+        detail     : detail.map(|e| e.to_string()),
+    }, None) {
+        println!("Failed to write audit event {:?}: {}", kind, e);
+    };
+}
+
+/*- Find events, newest first -*/
+pub(crate) fn query(query:&AuditQuery) -> Result<Vec<AuditEvent>, ()> {
+    let mut filter = Document::new();
+    if let Some(suid) = &query.suid { filter.insert("suid", suid); };
+    if let Some(kind) = query.kind {
+        filter.insert("kind", mongodb::bson::to_bson(&kind).map_err(|_| ())?);
+    };
+
+    /*- Time range -*/
+    let mut timestamp = Document::new();
+    if let Some(from) = query.from { timestamp.insert("$gte", from as i64); };
+    if let Some(to)   = query.to   { timestamp.insert("$lte", to as i64); };
+    if !timestamp.is_empty() { filter.insert("timestamp", timestamp); };
+
+    let options = FindOptions::builder()
+        .sort(doc!{ "timestamp": -1 })
+        .limit(query.limit.unwrap_or(100).clamp(1, MAX_QUERY_LIMIT))
+        .build();
+
+    let collection:Collection<AuditEvent> = utils::establish_mclient::<AuditEvent>(AUDIT_COLLECTION);
+    match collection.find(filter, options) {
+        Ok(cursor) => Ok(cursor.filter_map(|e| e.ok()).collect()),
+        Err(_)     => Err(()),
+    }
+}
+
+/*- Every event of a user, oldest first, for exporting their data -*/
+pub(crate) fn for_user(suid:&str) -> Vec<AuditEvent> {
+    let options = FindOptions::builder().sort(doc!{ "timestamp": 1 }).build();
+    let collection:Collection<AuditEvent> = utils::establish_mclient::<AuditEvent>(AUDIT_COLLECTION);
+    match collection.find(doc!{ "suid": suid }, options) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect(),
+        Err(_)     => vec![],
+    }
+}
@@ -0,0 +1,246 @@
+/*- Runtime configuration -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use lazy_static::lazy_static;
+use std::env;
+
+/*- Statics & Constants -*/
+/*- Only used when no keys are configured and ALLOW_DEVELOPMENT_KEY
+    is set, so that local development works. It's public, so
+    tokens signed with it can be forged by anyone -*/
+const DEVELOPMENT_SECRET_KEY:&str = "Secret123";
+
+/*- Names which could be mistaken for staff or routes -*/
+const DEFAULT_RESERVED_USERNAMES:&[&str] = &[
+    "admin", "administrator", "root", "system", "support", "moderator",
+    "staff", "security", "api", "login", "logout", "me", "null", "undefined",
+];
+
+lazy_static! {
+    pub(crate) static ref CONFIG:Config = Config::load();
+}
+
+/*- Structs, enums, unions -*/
+/// # Config
+/// Settings read from environment variables at startup.
+///
+/// * `JWT_SECRET_KEYS` - Comma separated `kid=secret` pairs. Every
+///   key listed here is accepted when verifying tokens.
+/// * `JWT_ASYMMETRIC_KEYS` - Comma separated `kid=ALG:private.pem:public.pem`
+///   entries, where `ALG` is `RS256` or `EdDSA`. The public halves of
+///   these keys are published at `/.well-known/jwks.json`.
+/// * `ALLOW_DEVELOPMENT_KEY` - `1` to sign with a public development key when no
+///   keys are configured. Without it, the server refuses to start without keys.
+///   Also lets `MAIL_TRANSPORT` default to `outbox`.
+/// * `JWT_SIGNING_KEY_ID` - The `kid` of the key used to sign new
+///   tokens. Defaults to the first asymmetric key, or else
+///   the first key in `JWT_SECRET_KEYS`.
+/// * `ACCESS_TOKEN_LIFETIME` - Seconds an access token is valid for.
+/// * `REFRESH_TOKEN_LIFETIME` - Seconds a refresh token is valid for.
+/// * `PUBLIC_URL` - Where clients reach this server, used for links in emails.
+///   Also the issuer of OpenID Connect ID tokens.
+/// * `OAUTH_CONSENT_URL` - Where the consent screen for third-party apps is served.
+///   Left out of the OpenID Connect discovery document if not set.
+/// * `MAIL_TRANSPORT` - `smtp`, or `outbox` to write mails to `MAIL_OUTBOX_DIR`. Required,
+///   unless `ALLOW_DEVELOPMENT_KEY` is set, in which case it defaults to `outbox`.
+/// * `MAIL_FROM` - The sender address of all mails.
+/// * `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD` - SMTP relay settings.
+/// * `SMTP_TLS` - `starttls`, `tls` or `none`.
+/// * `TOTP_ISSUER` - The name authenticator apps show for this service.
+/// * `TOTP_ENCRYPTION_KEY` - The secret 2FA secrets are encrypted with. 2FA can't be
+///   set up without it. Falls back to a development key with `ALLOW_DEVELOPMENT_KEY`.
+/// * `ADMIN_SUIDS` - Comma separated suids of users who are always admins.
+/// * `PASSWORD_MIN_LENGTH`, `PASSWORD_MAX_LENGTH` - Passwords must be at least the
+///   minimum, and less than the maximum number of characters long.
+/// * `PASSWORD_MIN_STRENGTH` - Required strength score, 0 (off) to 4.
+/// * `USERNAME_MIN_LENGTH`, `USERNAME_MAX_LENGTH` - Inclusive username length limits.
+/// * `USERNAME_CHARSET` - Regex character class contents usernames may consist of.
+/// * `RESERVED_USERNAMES` - Comma separated usernames nobody can register.
+/// * `ACCOUNT_DELETION_GRACE` - Seconds between asking for an account to be
+///   deleted and it being deleted. 0 deletes right away.
+/// * `REGISTRATION_MODE` - `open`, or `invite-only` to require an invite code.
+/// * `INVITE_QUOTA` - How many invite codes every user may create. Admins have no limit.
+/// * `MINIMUM_AGE` - Years old users must be to sign up. 0 doesn't ask for a date of birth.
+pub(crate) struct Config {
+    pub jwt_secret_keys        : Vec<(String, String)>,
+    pub jwt_asymmetric_keys    : Vec<AsymmetricKeyConfig>,
+    pub jwt_signing_kid        : Option<String>,
+    pub access_token_lifetime  : u64,
+    pub refresh_token_lifetime : u64,
+    pub public_url             : String,
+    pub oauth_consent_url      : Option<String>,
+    pub mail_transport         : String,
+    pub mail_from              : String,
+    pub mail_outbox_dir        : String,
+    pub smtp_host              : String,
+    pub smtp_port              : u16,
+    pub smtp_username          : Option<String>,
+    pub smtp_password          : Option<String>,
+    pub smtp_tls               : String,
+    pub totp_issuer            : String,
+    pub totp_encryption_key    : Option<String>,
+    pub admin_suids            : Vec<String>,
+    pub password_min_length    : usize,
+    pub password_max_length    : usize,
+    pub password_min_strength  : u8,
+    pub username_min_length    : usize,
+    pub username_max_length    : usize,
+    pub username_charset       : String,
+    pub reserved_usernames     : Vec<String>,
+    pub account_deletion_grace : u64,
+    pub invite_only            : bool,
+    pub invite_quota           : u64,
+    pub minimum_age            : u64,
+}
+
+/// # AsymmetricKeyConfig
+/// Where to find an RSA or Ed25519 key pair, stored as PEM files.
+pub(crate) struct AsymmetricKeyConfig {
+    pub kid              : String,
+    pub algorithm        : String,
+    pub private_key_path : String,
+    pub public_key_path  : String,
+}
+
+/*- Function implementations -*/
+impl Config {
+    pub fn from_env() -> Self {
+        /*- Parse the `kid=secret` pairs -*/
+        let mut jwt_secret_keys = env_list("JWT_SECRET_KEYS")
+            .iter()
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(kid, secret)| (kid.trim().to_string(), secret.trim().to_string()))
+            .collect::<Vec<(String, String)>>();
+
+        /*- Parse the `kid=ALG:private.pem:public.pem` entries -*/
+        let jwt_asymmetric_keys = env_list("JWT_ASYMMETRIC_KEYS")
+            .iter()
+            .filter_map(|entry| {
+                let (kid, rest) = entry.split_once('=')?;
+                let mut parts = rest.splitn(3, ':');
+
+                Some(AsymmetricKeyConfig {
+                    kid              : kid.trim().to_string(),
+                    algorithm        : parts.next()?.trim().to_string(),
+                    private_key_path : parts.next()?.trim().to_string(),
+                    public_key_path  : parts.next()?.trim().to_string(),
+                })
+            })
+            .collect::<Vec<AsymmetricKeyConfig>>();
+
+        /*- Only fall back to the development keys if asked to -*/
+        let allow_development_key = env_or("ALLOW_DEVELOPMENT_KEY", "0") == "1";
+        if jwt_secret_keys.is_empty() && jwt_asymmetric_keys.is_empty() {
+            if !allow_development_key {
+                println!("No JWT keys are configured. Set JWT_SECRET_KEYS or JWT_ASYMMETRIC_KEYS, or ALLOW_DEVELOPMENT_KEY=1 for local development.");
+                std::process::exit(1);
+            };
+
+            println!("Warning: No JWT keys are configured, using the public development key.");
+            jwt_secret_keys.push(("development".to_string(), DEVELOPMENT_SECRET_KEY.to_string()));
+        };
+
+        /*- Mails are only written to disk instead of being sent when asked
+            to, or during local development, so that they aren't lost silently -*/
+        let mail_outbox_dir = env_or("MAIL_OUTBOX_DIR", "outbox");
+        let mail_transport = match env_opt("MAIL_TRANSPORT") {
+            Some(mail_transport) => mail_transport,
+            None if allow_development_key => "outbox".to_string(),
+            None => {
+                println!("No MAIL_TRANSPORT is configured. Set it to smtp, or to outbox to write mails to MAIL_OUTBOX_DIR.");
+                std::process::exit(1);
+            },
+        };
+        if mail_transport == "outbox" {
+            println!("Warning: Mails are written to {} instead of being sent.", mail_outbox_dir);
+        };
+
+        let public_url = env_or("PUBLIC_URL", "http://127.0.0.1:8000").trim_end_matches('/').to_string();
+
+        Config {
+            jwt_secret_keys,
+            jwt_asymmetric_keys,
+            jwt_signing_kid        : env_opt("JWT_SIGNING_KEY_ID"),
+            access_token_lifetime  : env_u64("ACCESS_TOKEN_LIFETIME", 60*15),
+            refresh_token_lifetime : env_u64("REFRESH_TOKEN_LIFETIME", 60*60*24*30),
+            oauth_consent_url      : env_opt("OAUTH_CONSENT_URL"),
+            public_url,
+            mail_transport,
+            mail_from              : env_or("MAIL_FROM", "Account Manager <no-reply@localhost>"),
+            mail_outbox_dir,
+            smtp_host              : env_or("SMTP_HOST", "localhost"),
+            smtp_port              : env_u64("SMTP_PORT", 587) as u16,
+            smtp_username          : env_opt("SMTP_USERNAME"),
+            smtp_password          : env_opt("SMTP_PASSWORD"),
+            smtp_tls               : env_or("SMTP_TLS", "starttls"),
+            totp_issuer            : env_or("TOTP_ISSUER", "Account Manager"),
+            totp_encryption_key    : env_opt("TOTP_ENCRYPTION_KEY")
+                .or_else(|| Some(DEVELOPMENT_SECRET_KEY.to_string()).filter(|_| allow_development_key)),
+            admin_suids            : env_list("ADMIN_SUIDS"),
+            password_min_length    : env_u64("PASSWORD_MIN_LENGTH", 8) as usize,
+            password_max_length    : env_u64("PASSWORD_MAX_LENGTH", 128) as usize,
+            password_min_strength  : env_u64("PASSWORD_MIN_STRENGTH", 0).min(4) as u8,
+            username_min_length    : env_u64("USERNAME_MIN_LENGTH", 3) as usize,
+            username_max_length    : env_u64("USERNAME_MAX_LENGTH", 20) as usize,
+            username_charset       : env_or("USERNAME_CHARSET", "a-zA-Z0-9_."),
+            reserved_usernames     : match env_opt("RESERVED_USERNAMES") {
+                Some(_) => env_list("RESERVED_USERNAMES"),
+                None    => DEFAULT_RESERVED_USERNAMES.iter().map(|e| e.to_string()).collect(),
+            },
+            account_deletion_grace : env_u64("ACCOUNT_DELETION_GRACE", 0),
+            invite_only            : env_or("REGISTRATION_MODE", "open") == "invite-only",
+            invite_quota           : env_u64("INVITE_QUOTA", 0),
+            minimum_age            : env_u64("MINIMUM_AGE", 0),
+        }
+    }
+
+    #[cfg(not(test))]
+    fn load() -> Self {
+        Self::from_env()
+    }
+
+    /*- The configuration unit tests run with. Like a local development
+        setup, so that they don't need keys. This runs once, when CONFIG
+        is first used, so setting the variable can't race with reading it -*/
+    #[cfg(test)]
+    fn load() -> Self {
+        env::set_var("ALLOW_DEVELOPMENT_KEY", "1");
+        Self::from_env()
+    }
+}
+
+/*- Get an optional, non-empty environment variable -*/
+pub(crate) fn env_opt(name:&str) -> Option<String> {
+    match env::var(name) {
+        Ok(value) if !value.trim().is_empty() => Some(value.trim().to_string()),
+        _ => None,
+    }
+}
+
+/*- Get an environment variable, or a default -*/
+pub(crate) fn env_or(name:&str, default:&str) -> String {
+    env_opt(name).unwrap_or(default.to_string())
+}
+
+/*- Get a numeric environment variable, or a default -*/
+pub(crate) fn env_u64(name:&str, default:u64) -> u64 {
+    env_opt(name)
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
+/*- Get a comma separated environment variable as a list -*/
+pub(crate) fn env_list(name:&str) -> Vec<String> {
+    env_opt(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty())
+        .collect::<Vec<String>>()
+}
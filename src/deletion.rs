@@ -0,0 +1,116 @@
+/*- Account deletion -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, tokens, sessions, api_keys, oauth, password_reset, magic_link, totp, invites, exports, profile, config::CONFIG, user::User, tweet::Tweet };
+use std::{ fs, thread, time::Duration };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+/*- How often scheduled deletions are looked for -*/
+const PURGE_INTERVAL:u64 = 60;
+
+/*- Functions -*/
+/*- Delete an account, either right away or after the grace period -*/
+pub(crate) fn delete_account(user:&User) -> Result<(), ()> {
+    /*- Log out everywhere, whichever way it's deleted -*/
+    tokens::revoke_all_for_user(&user.suid);
+    api_keys::delete_api_keys(&user.suid);
+
+    match CONFIG.account_deletion_grace {
+        0 => purge_account(user),
+        grace => {
+            let collection:Collection<User> = utils::establish_mclient::<User>("test");
+            collection.update_one(
+                doc!{ "suid": &user.suid },
+                doc!{ "$set": { "deletion_scheduled": (utils::get_unix_epoch_time() + grace) as i64 } },
+                None
+            ).map(|_| ()).map_err(|_| ())
+        }
+    }
+}
+
+/*- Call off a scheduled deletion -*/
+pub(crate) fn cancel_deletion(suid:&str) -> () {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    collection.update_one(
+        doc!{ "suid": suid },
+        doc!{ "$set": { "deletion_scheduled": null } },
+        None
+    ).ok();
+}
+
+/*- Remove the user and everything belonging to them -*/
+pub(crate) fn purge_account(user:&User) -> Result<(), ()> {
+    let tweets:Collection<Tweet> = utils::establish_mclient::<Tweet>("tweets");
+
+    /*- Their tweets -*/
+    tweets.delete_many(doc!{ "owner": &user.suid }, None).map_err(|_| ())?;
+
+    /*- Their likes on other users' tweets -*/
+    tweets.update_many(
+        doc!{ "likes": &user.suid },
+        doc!{ "$pull": { "likes": &user.suid } },
+        None
+    ).map_err(|_| ())?;
+
+    /*- Their profile image and banner, which may not exist, and data exports -*/
+    fs::remove_file(format!("uploads/{}.jpg", user.suid)).ok();
+    profile::delete_banner(&user.suid);
+    exports::delete_exports(&user.suid);
+
+    /*- Pending tokens -*/
+    tokens::revoke_all_for_user(&user.suid);
+    tokens::delete_refresh_tokens(&user.suid);
+    sessions::delete_sessions(&user.suid);
+    api_keys::delete_api_keys(&user.suid);
+    password_reset::delete_password_resets(&user.suid);
+    magic_link::delete_magic_links(&user.suid);
+    totp::delete_challenges(&user.suid);
+    oauth::delete_codes(&user.suid);
+
+    /*- Apps they have registered, and invites they have created -*/
+    oauth::delete_clients(&user.suid);
+    invites::delete_invites(&user.suid);
+
+    /*- And lastly the user -*/
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    collection.delete_one(doc!{ "suid": &user.suid }, None).map(|_| ()).map_err(|_| ())
+}
+
+/*- Purge every account whose grace period has passed -*/
+pub(crate) fn purge_due_accounts() -> () {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let due = match collection.find(
+        doc!{ "deletion_scheduled": { "$lte": utils::get_unix_epoch_time() as i64 } },
+        None
+    ) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect::<Vec<User>>(),
+        Err(_) => return,
+    };
+
+    for user in due {
+        if purge_account(&user).is_err() {
+            println!("Failed to purge account {}", user.suid);
+        };
+    };
+}
+
+/*- Periodically purge due accounts in the background -*/
+pub(crate) fn spawn_purger() -> () {
+    /*- Nothing is ever scheduled without a grace period -*/
+    if CONFIG.account_deletion_grace == 0 { return; };
+
+    thread::spawn(|| loop {
+        purge_due_accounts();
+        thread::sleep(Duration::from_secs(PURGE_INTERVAL));
+    });
+}
@@ -0,0 +1,251 @@
+/*- Personal data exports -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, sessions, audit, api_keys, invites, profile, config::CONFIG, user::{ User, get_user, generate_suid }, tweet::Tweet, mail::{ MAILER, Mail } };
+use serde::{ Serialize, Deserialize };
+use std::{ fs, io::Write, thread };
+use zip::{ ZipWriter, CompressionMethod, write::FileOptions };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const EXPORT_COLLECTION:&str = "exports";
+const EXPORT_DIR:&str = "exports";
+
+/*- How long an archive can be downloaded for -*/
+const EXPORT_LIFETIME:u64 = 60*60*24*2;
+
+/*- Fields of the user which are credentials rather than data about them -*/
+const CREDENTIAL_FIELDS:&[&str] = &["password", "totp_secret", "recovery_codes"];
+
+/*- Structs, enums, unions -*/
+/// # ExportStatus
+/// Where an export is at. Archives are built in the background.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// # Export
+/// A requested archive of everything stored about a user. It's
+/// downloaded with a token, of which only the hash is stored.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Export {
+    pub id         : String,
+    pub suid       : String,
+    pub token_hash : String,
+    pub status     : ExportStatus,
+    pub created    : u64,
+    pub expires    : u64,
+}
+
+/*- Functions -*/
+/*- Start building an archive for a user. Returns the export and
+    the token it's downloaded with, and mails the link when it's ready -*/
+pub(crate) fn request_export(user:&User) -> Result<(Export, String), ()> {
+    let token = utils::generate_token();
+    let now = utils::get_unix_epoch_time();
+
+    /*- Only the latest archive is kept, and none which have expired -*/
+    delete_exports(&user.suid);
+    delete_expired_exports();
+
+    let export = Export {
+        id         : generate_suid(),
+        suid       : user.suid.clone(),
+        token_hash : utils::hash(&token),
+        status     : ExportStatus::Pending,
+        created    : now,
+        expires    : now + EXPORT_LIFETIME,
+    };
+
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    collection.insert_one(&export, None).map_err(|_| ())?;
+
+    /*- Build it in the background -*/
+    let (id, user, link) = (export.id.clone(), user.clone(), download_link(&token));
+    thread::spawn(move || {
+        let status = match build_archive(&user, &archive_path(&id)) {
+            Ok(_) => ExportStatus::Ready,
+            Err(e) => {
+                println!("Failed to build export {}: {}", id, e);
+                ExportStatus::Failed
+            }
+        };
+
+        let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+        collection.update_one(
+            doc!{ "id": &id },
+            doc!{ "$set": { "status": mongodb::bson::to_bson(&status).unwrap() } },
+            None
+        ).ok();
+
+        if status == ExportStatus::Ready {
+            if let Err(e) = send_export_mail(&user, &link) {
+                println!("Failed to send export mail: {}", e);
+            };
+        };
+    });
+
+    Ok((export, token))
+}
+
+/*- If an archive is being built for a user -*/
+pub(crate) fn is_pending(suid:&str) -> bool {
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    let filter = doc!{
+        "suid"    : suid,
+        "status"  : "pending",
+        "expires" : { "$gt": utils::get_unix_epoch_time() as i64 },
+    };
+    matches!(collection.find_one(filter, None), Ok(Some(_)))
+}
+
+/*- Archives are built in threads of this process, so any still
+    pending at startup were interrupted and will never be ready -*/
+pub(crate) fn fail_interrupted_exports() -> () {
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    collection.update_many(
+        doc!{ "status": "pending" },
+        doc!{ "$set": { "status": mongodb::bson::to_bson(&ExportStatus::Failed).unwrap() } },
+        None
+    ).ok();
+}
+
+/*- Where an archive is downloaded from -*/
+pub(crate) fn download_link(token:&str) -> String {
+    format!("{}/export/{}", CONFIG.public_url, token)
+}
+
+/*- Get one of a user's exports -*/
+pub(crate) fn get_export(suid:&str, id:&str) -> Option<Export> {
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    collection.find_one(doc!{ "suid": suid, "id": id }, None).ok().flatten()
+}
+
+/*- Read the archive a download token belongs to. Errors
+    if the token is unknown, expired or not ready yet -*/
+pub(crate) fn read_archive(token:&str) -> Result<Vec<u8>, Option<ExportStatus>> {
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    let export = match collection.find_one(doc!{ "token_hash": utils::hash(token) }, None) {
+        Ok(Some(export)) if export.expires >= utils::get_unix_epoch_time() => export,
+        _ => return Err(None),
+    };
+
+    match export.status {
+        ExportStatus::Ready => fs::read(archive_path(&export.id)).map_err(|_| None),
+        status => Err(Some(status)),
+    }
+}
+
+/*- Remove a user's exports and their archives -*/
+pub(crate) fn delete_exports(suid:&str) -> () {
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    if let Ok(cursor) = collection.find(doc!{ "suid": suid }, None) {
+        for export in cursor.filter_map(|e| e.ok()) {
+            fs::remove_file(archive_path(&export.id)).ok();
+        };
+    };
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
+
+/*- Remove the archives which can't be downloaded anymore -*/
+pub(crate) fn delete_expired_exports() -> () {
+    let collection:Collection<Export> = utils::establish_mclient::<Export>(EXPORT_COLLECTION);
+    let filter = doc!{ "expires": { "$lt": utils::get_unix_epoch_time() as i64 } };
+    if let Ok(cursor) = collection.find(filter.clone(), None) {
+        for export in cursor.filter_map(|e| e.ok()) {
+            fs::remove_file(archive_path(&export.id)).ok();
+        };
+    };
+    collection.delete_many(filter, None).ok();
+}
+
+/*- Where an archive is stored -*/
+fn archive_path(id:&str) -> String {
+    format!("{}/{}.zip", EXPORT_DIR, id)
+}
+
+/*- Write everything stored about a user to a zip archive -*/
+fn build_archive(user:&User, path:&str) -> Result<(), String> {
+    let tweets:Collection<Tweet> = utils::establish_mclient::<Tweet>("tweets");
+    let find_tweets = |filter| match tweets.find(filter, None) {
+        Ok(cursor) => Ok(cursor.filter_map(|e| e.ok()).collect::<Vec<Tweet>>()),
+        Err(e)     => Err(e.to_string()),
+    };
+
+    /*- The user, without their credentials -*/
+    let mut account = serde_json::to_value(user).map_err(|e| e.to_string())?;
+    if let Some(account) = account.as_object_mut() {
+        for field in CREDENTIAL_FIELDS { account.remove(*field); };
+    };
+
+    /*- Only what identifies their API keys, not the hashes -*/
+    let api_keys = api_keys::list(&user.suid).into_iter().map(|api_key| serde_json::json!({
+        "id"        : api_key.id,
+        "name"      : api_key.name,
+        "prefix"    : api_key.prefix,
+        "scopes"    : api_key.scopes,
+        "created"   : api_key.created,
+        "expires"   : api_key.expires,
+        "last_used" : api_key.last_used,
+    })).collect::<Vec<serde_json::Value>>();
+
+    let documents:Vec<(&str, serde_json::Value)> = vec![
+        ("user.json",         account),
+        ("tweets.json",       serde_json::to_value(find_tweets(doc!{ "owner": &user.suid })?).unwrap()),
+        ("liked_tweets.json", serde_json::to_value(find_tweets(doc!{ "likes": &user.suid })?).unwrap()),
+        ("sessions.json",     serde_json::to_value(sessions::all(&user.suid)).unwrap()),
+        ("audit_events.json", serde_json::to_value(audit::for_user(&user.suid)).unwrap()),
+        ("api_keys.json",     serde_json::Value::Array(api_keys)),
+        ("invites.json",      serde_json::to_value(invites::list(&user.suid)).unwrap()),
+    ];
+
+    /*- Write the archive -*/
+    fs::create_dir_all(EXPORT_DIR).map_err(|e| e.to_string())?;
+    let mut archive = ZipWriter::new(fs::File::create(path).map_err(|e| e.to_string())?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, document) in documents {
+        archive.start_file(name, options).map_err(|e| e.to_string())?;
+        archive.write_all(serde_json::to_string_pretty(&document).unwrap().as_bytes()).map_err(|e| e.to_string())?;
+    };
+
+    /*- Their profile image and banner, which may not exist -*/
+    let images = [
+        ("profile_image.jpg", format!("uploads/{}.jpg", user.suid)),
+        ("banner.jpg",        profile::banner_path(&user.suid)),
+    ];
+    for (name, path) in images {
+        if let Ok(image) = fs::read(path) {
+            archive.start_file(name, options).map_err(|e| e.to_string())?;
+            archive.write_all(&image).map_err(|e| e.to_string())?;
+        };
+    };
+
+    archive.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/*- Tell the user their archive can be downloaded -*/
+fn send_export_mail(user:&User, link:&str) -> Result<(), String> {
+    MAILER.send(&Mail {
+        to      : user.email.clone(),
+        subject : "Your data export is ready".to_string(),
+        body    : format!(
+            "Hi {},\r\n\r\nThe archive of your data you asked for is ready. Download it from the link below.\r\n\r\n{}\r\n\r\nThe link is valid for 48 hours.",
+            user.displayname, link
+        ),
+    })
+}
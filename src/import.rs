@@ -0,0 +1,181 @@
+/*- Bulk importing accounts from other systems -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, policy, password, dict::DICTIONARY };
+use crate::audit::{ self, EventKind, Outcome };
+use crate::user::{ User, normalize_identifier, check_email, generate_uuid, generate_suid, in_use_message };
+use serde::{ Serialize, Deserialize };
+use std::{ fs, collections::HashSet };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const USAGE:&str = "Usage: rust-account-manager import <file.csv|file.jsonl> [--dry-run]";
+
+/*- What imported accounts are recorded as created by in the audit log -*/
+const AUDIT_SOURCE:&str = "rust-account-manager import";
+
+/*- Structs, enums, unions -*/
+/// # ImportRecord
+/// One account to import, a CSV row or a JSON line. Passwords can
+/// only be imported already hashed, as Argon2 PHC strings or legacy
+/// SHA3 digests. Accounts without one have to reset their password
+/// or log in with a login link. Like at signup, the date of birth is
+/// required if there is a minimum age.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ImportRecord {
+    pub username       : String,
+    pub email          : String,
+    #[serde(default)]
+    pub displayname    : Option<String>,
+    #[serde(default)]
+    pub password_hash  : Option<String>,
+    #[serde(default)]
+    pub email_verified : bool,
+    #[serde(default)]
+    pub date_of_birth  : Option<String>,
+}
+
+/// # ImportResult
+/// What happened to one record, printed as a line of the report.
+#[derive(Serialize, Debug)]
+pub(crate) struct ImportResult {
+    pub row   : usize,
+    pub ok    : bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suid  : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error : Option<String>,
+}
+
+/*- Functions -*/
+/*- Run the import command with the arguments after "import" -*/
+pub(crate) fn run(args:&[String]) -> () {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let path = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(path) => path,
+        None => return println!("{}", USAGE),
+    };
+
+    let records = match read_records(path) {
+        Ok(records) => records,
+        Err(e) => return println!("Failed to read {}: {}", path, e),
+    };
+
+    /*- One JSON line per record, then a summary -*/
+    let results = import(records, dry_run);
+    for result in &results {
+        println!("{}", serde_json::to_string(result).unwrap());
+    };
+
+    let imported = results.iter().filter(|result| result.ok).count();
+    println!(
+        "{} of {} accounts {}",
+        imported, results.len(), if dry_run { "would be imported (dry run)" } else { "imported" }
+    );
+}
+
+/*- Read the records of a CSV file (with a header row), or of a file
+    with a JSON object per line. Records which can't be parsed are
+    kept as errors, so that they show up in the report -*/
+fn read_records(path:&str) -> Result<Vec<Result<ImportRecord, String>>, String> {
+    if path.ends_with(".csv") {
+        let mut reader = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
+        return Ok(reader.deserialize::<ImportRecord>().map(|record| record.map_err(|e| e.to_string())).collect());
+    };
+
+    Ok(fs::read_to_string(path).map_err(|e| e.to_string())?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str::<ImportRecord>(line).map_err(|e| e.to_string()))
+        .collect())
+}
+
+/*- Validate and insert the records. Nothing is inserted in a dry run -*/
+fn import(records:Vec<Result<ImportRecord, String>>, dry_run:bool) -> Vec<ImportResult> {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+
+    /*- Records must also be unique among each other -*/
+    let mut usernames:HashSet<String> = HashSet::new();
+    let mut emails:HashSet<String> = HashSet::new();
+
+    records.into_iter().enumerate().map(|(index, record)| {
+        let result = record
+            .and_then(|record| validate(record, &collection, &mut usernames, &mut emails))
+            .and_then(|user| match dry_run {
+                true  => Ok(user.suid),
+                false => insert(user, &collection),
+            });
+
+        match result {
+            Ok(suid)   => ImportResult { row: index + 1, ok: true, suid: Some(suid), error: None },
+            Err(error) => ImportResult { row: index + 1, ok: false, suid: None, error: Some(error) },
+        }
+    }).collect()
+}
+
+/*- Check a record with the rules of /create-account, and turn it into a user -*/
+fn validate(
+    record    : ImportRecord,
+    collection: &Collection<User>,
+    usernames : &mut HashSet<String>,
+    emails    : &mut HashSet<String>
+) -> Result<User, String> {
+    let username = normalize_identifier(&record.username);
+    let email    = normalize_identifier(&record.email);
+
+    policy::check_username(&username).map_err(|violation| violation.message())?;
+    if !check_email(&email) { return Err(DICTIONARY.error.invalid.email.to_string()); };
+
+    /*- Unique in the database and in the import -*/
+    if usernames.contains(&username) || collection.find_one(doc!{ "username": &username }, None).ok().flatten().is_some() {
+        return Err(DICTIONARY.error.in_use.username.to_string());
+    };
+    if emails.contains(&email) || collection.find_one(doc!{ "email": &email }, None).ok().flatten().is_some() {
+        return Err(DICTIONARY.error.in_use.email.to_string());
+    };
+
+    let date_of_birth = policy::check_date_of_birth(record.date_of_birth.as_deref()).map_err(|violation| violation.message())?;
+
+    /*- Without a hash, nobody knows the password -*/
+    let password = match record.password_hash {
+        Some(hash) if password::is_supported_hash(&hash) => hash,
+        Some(_) => return Err(DICTIONARY.error.invalid.password_hash.to_string()),
+        None    => password::hash_password(&utils::generate_token()),
+    };
+
+    usernames.insert(username.clone());
+    emails.insert(email.clone());
+
+    Ok(User {
+        displayname    : record.displayname.unwrap_or_else(|| username.clone()),
+        username,
+        password,
+        email,
+        email_verified : record.email_verified,
+        date_of_birth,
+        created        : Some(utils::get_unix_epoch_time()),
+        uid            : generate_uuid(),
+        suid           : generate_suid(),
+        ..User::default()
+    })
+}
+
+/*- Insert a validated user, returning their suid -*/
+fn insert(user:User, collection:&Collection<User>) -> Result<String, String> {
+    match collection.insert_one(&user, None) {
+        Ok(_) => {
+            audit::record_local(AUDIT_SOURCE, EventKind::AccountCreated, Outcome::Success, Some(&user.suid), Some("import"));
+            Ok(user.suid)
+        },
+        Err(e) => Err(in_use_message(&e).map(|message| message.to_string()).unwrap_or_else(|| e.to_string())),
+    }
+}
@@ -0,0 +1,109 @@
+/*- Invite codes, for invite-only registration -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::utils;
+use serde::{ Serialize, Deserialize };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const INVITE_COLLECTION:&str = "invites";
+
+/*- Invite codes are typed in by hand, so they're short -*/
+const INVITE_CODE_LENGTH:usize = 12;
+
+/*- Structs, enums, unions -*/
+/// # Invite
+/// A code which lets people create an account. Codes made by admins
+/// can be used several times, those made by users only once. Unlike
+/// other tokens the code is stored as is, so that whoever created
+/// it can look it up again to pass it on.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Invite {
+    pub code       : String,
+    pub created_by : String,
+    pub max_uses   : u64,
+    pub used_by    : Vec<String>,
+    pub created    : u64,
+    pub expires    : Option<u64>,
+}
+
+/*- Functions -*/
+/*- Create an invite code -*/
+pub(crate) fn create_invite(created_by:&str, max_uses:u64, expires:Option<u64>) -> Result<Invite, ()> {
+    let invite = Invite {
+        code       : utils::generate_token()[..INVITE_CODE_LENGTH].to_string(),
+        created_by : created_by.to_string(),
+        max_uses,
+        used_by    : vec![],
+        created    : utils::get_unix_epoch_time(),
+        expires,
+    };
+
+    let collection:Collection<Invite> = utils::establish_mclient::<Invite>(INVITE_COLLECTION);
+    collection.insert_one(&invite, None).map_err(|_| ())?;
+
+    Ok(invite)
+}
+
+/*- Every invite a user has created -*/
+pub(crate) fn list(created_by:&str) -> Vec<Invite> {
+    let collection:Collection<Invite> = utils::establish_mclient::<Invite>(INVITE_COLLECTION);
+    match collection.find(doc!{ "created_by": created_by }, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect(),
+        Err(_)     => vec![],
+    }
+}
+
+/*- How many invites a user has created -*/
+pub(crate) fn count(created_by:&str) -> u64 {
+    let collection:Collection<Invite> = utils::establish_mclient::<Invite>(INVITE_COLLECTION);
+    collection.count_documents(doc!{ "created_by": created_by }, None).unwrap_or(u64::MAX)
+}
+
+/*- Use an invite for a new user. Fails if the code doesn't
+    exist, has expired or has been used up -*/
+pub(crate) fn redeem(code:&str, suid:&str) -> Result<Invite, ()> {
+    let collection:Collection<Invite> = utils::establish_mclient::<Invite>(INVITE_COLLECTION);
+
+    /*- Atomically, so that a code can't be used more often than allowed -*/
+    let invite = collection.find_one_and_update(
+        doc!{
+            "code"  : code.trim(),
+            "$expr" : { "$lt": [{ "$size": "$used_by" }, "$max_uses"] },
+            "$or"   : [
+                { "expires": null },
+                { "expires": { "$gt": utils::get_unix_epoch_time() as i64 } },
+            ],
+        },
+        doc!{ "$push": { "used_by": suid } },
+        None
+    ).map_err(|_| ())?;
+
+    invite.ok_or(())
+}
+
+/*- Give back a use of an invite, if the account couldn't be created after all -*/
+pub(crate) fn release(code:&str, suid:&str) -> () {
+    let collection:Collection<Invite> = utils::establish_mclient::<Invite>(INVITE_COLLECTION);
+    collection.update_one(
+        doc!{ "code": code.trim() },
+        doc!{ "$pull": { "used_by": suid } },
+        None
+    ).ok();
+}
+
+/*- Delete every invite a user has created, used when deleting them.
+    Who they have invited is still known from the invited users -*/
+pub(crate) fn delete_invites(created_by:&str) -> () {
+    let collection:Collection<Invite> = utils::establish_mclient::<Invite>(INVITE_COLLECTION);
+    collection.delete_many(doc!{ "created_by": created_by }, None).ok();
+}
@@ -0,0 +1,261 @@
+/*- JWT signing & verification keys -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::config::{ CONFIG, AsymmetricKeyConfig };
+use lazy_static::lazy_static;
+use std::fs;
+use simple_asn1::{ from_der, ASN1Block };
+use serde::{ Serialize, de::DeserializeOwned };
+use jsonwebtoken::{ encode, decode, decode_header, Header, Algorithm, Validation, EncodingKey, DecodingKey };
+
+/*- Statics & Constants -*/
+lazy_static! {
+    pub(crate) static ref KEYRING:KeyRing = KeyRing::from_config();
+}
+
+/*- Structs, enums, unions -*/
+/// # JwtKey
+/// A single key, identified by the `kid` header of the tokens it signs.
+/// Only asymmetric keys have a `jwk`, as shared secrets must never be published.
+pub(crate) struct JwtKey {
+    pub kid       : String,
+    pub algorithm : Algorithm,
+    pub encoding  : EncodingKey,
+    pub decoding  : DecodingKey,
+    pub jwk       : Option<Jwk>,
+}
+
+/// # Jwk
+/// The public part of a key, as described in RFC 7517.
+/// RSA keys use `n` and `e`, Ed25519 keys use `crv` and `x`.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct Jwk {
+    pub kty : String,
+    #[serde(rename = "use")]
+    pub use_: String,
+    pub alg : String,
+    pub kid : String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n   : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e   : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x   : Option<String>,
+}
+
+/// # JwkSet
+/// What gets served at `/.well-known/jwks.json`.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// # KeyRing
+/// All keys that tokens are verified against, and which one of them
+/// new tokens are signed with. Rotating the signing key is done by
+/// adding a new key and pointing `JWT_SIGNING_KEY_ID` at it. The old
+/// key should stay in the ring until the tokens it signed have expired.
+pub(crate) struct KeyRing {
+    pub signing_kid : String,
+    pub keys        : Vec<JwtKey>,
+}
+
+/*- Function implementations -*/
+impl KeyRing {
+    /*- Build the keyring from the config -*/
+    pub fn from_config() -> Self {
+        /*- Asymmetric keys come first, so that they're
+            preferred for signing when no kid is configured -*/
+        let mut keys = CONFIG.jwt_asymmetric_keys
+            .iter()
+            .map(load_asymmetric_key)
+            .collect::<Vec<JwtKey>>();
+
+        /*- Shared secrets -*/
+        keys.extend(CONFIG.jwt_secret_keys
+            .iter()
+            .map(|(kid, secret)| JwtKey {
+                kid       : kid.clone(),
+                algorithm : Algorithm::HS256,
+                encoding  : EncodingKey::from_secret(secret.as_bytes()),
+                decoding  : DecodingKey::from_secret(secret.as_bytes()),
+                jwk       : None,
+            }));
+
+        /*- Default to the first key -*/
+        let signing_kid = CONFIG.jwt_signing_kid
+            .clone()
+            .unwrap_or(keys[0].kid.clone());
+
+        /*- The signing key must also be a verification key -*/
+        if !keys.iter().any(|key| key.kid == signing_kid) {
+            panic!("JWT signing key \"{}\" is not among the configured keys", signing_kid);
+        };
+
+        KeyRing { signing_kid, keys }
+    }
+
+    /*- Get the key new tokens are signed with -*/
+    pub fn signing_key(&self) -> &JwtKey {
+        self.get(&self.signing_kid).expect("Signing key missing from keyring")
+    }
+
+    /*- Get the key ID tokens are signed with. Apps have to verify those
+        with the JWKS, so only asymmetric keys will do. Prefers the
+        signing key, falling back to the first asymmetric key -*/
+    pub fn id_token_key(&self) -> Option<&JwtKey> {
+        match self.signing_key() {
+            key if key.jwk.is_some() => Some(key),
+            _ => self.keys.iter().find(|key| key.jwk.is_some()),
+        }
+    }
+
+    /*- Get a key by its id -*/
+    pub fn get(&self, kid:&str) -> Option<&JwtKey> {
+        self.keys.iter().find(|key| key.kid == kid)
+    }
+
+    /*- The public keys other services can verify our tokens with -*/
+    pub fn jwk_set(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter().filter_map(|key| key.jwk.clone()).collect()
+        }
+    }
+}
+
+/*- Read an RSA or Ed25519 key pair from its PEM files -*/
+fn load_asymmetric_key(config:&AsymmetricKeyConfig) -> JwtKey {
+    let private_pem = fs::read(&config.private_key_path)
+        .unwrap_or_else(|_| panic!("Failed to read JWT private key {}", config.private_key_path));
+    let public_pem  = fs::read(&config.public_key_path)
+        .unwrap_or_else(|_| panic!("Failed to read JWT public key {}", config.public_key_path));
+
+    /*- Parse the keys depending on the algorithm -*/
+    let (algorithm, encoding, decoding) = match config.algorithm.as_str() {
+        "RS256" => (
+            Algorithm::RS256,
+            EncodingKey::from_rsa_pem(&private_pem),
+            DecodingKey::from_rsa_pem(&public_pem),
+        ),
+        "EdDSA" => (
+            Algorithm::EdDSA,
+            EncodingKey::from_ed_pem(&private_pem),
+            DecodingKey::from_ed_pem(&public_pem),
+        ),
+        other => panic!("Unsupported JWT algorithm \"{}\" for key \"{}\"", other, config.kid),
+    };
+
+    /*- Build the public JWK -*/
+    let jwk = public_jwk(&config.kid, algorithm, &public_pem)
+        .unwrap_or_else(|| panic!("Failed to parse JWT public key {}", config.public_key_path));
+
+    JwtKey {
+        kid      : config.kid.clone(),
+        algorithm,
+        encoding : encoding.unwrap_or_else(|_| panic!("Invalid JWT private key {}", config.private_key_path)),
+        decoding : decoding.unwrap_or_else(|_| panic!("Invalid JWT public key {}", config.public_key_path)),
+        jwk      : Some(jwk),
+    }
+}
+
+/*- Extract the JWK parameters from a SubjectPublicKeyInfo PEM -*/
+fn public_jwk(kid:&str, algorithm:Algorithm, public_pem:&[u8]) -> Option<Jwk> {
+    let der = pem::parse(public_pem).ok()?.contents;
+
+    /*- SubjectPublicKeyInfo ::= SEQUENCE { algorithm, subjectPublicKey BIT STRING } -*/
+    let public_key:Vec<u8> = match from_der(&der).ok()?.first()? {
+        ASN1Block::Sequence(_, blocks) => match blocks.get(1)? {
+            ASN1Block::BitString(_, _, bytes) => bytes.clone(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let mut jwk = Jwk {
+        kty : String::new(),
+        use_: "sig".to_string(),
+        alg : String::new(),
+        kid : kid.to_string(),
+        n   : None,
+        e   : None,
+        crv : None,
+        x   : None,
+    };
+
+    match algorithm {
+        /*- RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER } -*/
+        Algorithm::RS256 => {
+            let (modulus, exponent) = match from_der(&public_key).ok()?.first()? {
+                ASN1Block::Sequence(_, blocks) => match (blocks.get(0)?, blocks.get(1)?) {
+                    (ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)) => (n.to_bytes_be().1, e.to_bytes_be().1),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+
+            jwk.kty = "RSA".to_string();
+            jwk.alg = "RS256".to_string();
+            jwk.n   = Some(base64_url(&modulus));
+            jwk.e   = Some(base64_url(&exponent));
+        },
+
+        /*- Ed25519 public keys are the raw 32 bytes -*/
+        Algorithm::EdDSA => {
+            jwk.kty = "OKP".to_string();
+            jwk.alg = "EdDSA".to_string();
+            jwk.crv = Some("Ed25519".to_string());
+            jwk.x   = Some(base64_url(&public_key));
+        },
+        _ => return None,
+    };
+
+    Some(jwk)
+}
+
+/*- Unpadded base64url, as used in JWKs -*/
+fn base64_url(bytes:&[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/*- Sign some claims with the current signing key -*/
+pub(crate) fn sign<Claims:Serialize>(claims:&Claims) -> String {
+    sign_with(KEYRING.signing_key(), claims)
+}
+
+/*- Sign some claims with a specific key -*/
+pub(crate) fn sign_with<Claims:Serialize>(key:&JwtKey, claims:&Claims) -> String {
+    /*- Include the key id so verifiers know which key to use -*/
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, claims, &key.encoding).expect("Failed to encode token")
+}
+
+/*- Verify a token and return its claims -*/
+pub(crate) fn verify<Claims:DeserializeOwned>(token:&str) -> Result<Claims, ()> {
+    let header = decode_header(token).map_err(|_| ())?;
+
+    /*- Tokens issued before key ids were introduced
+        have no kid, so every key is tried for them -*/
+    let candidates:Vec<&JwtKey> = match header.kid {
+        Some(kid) => KEYRING.get(&kid).into_iter().collect(),
+        None      => KEYRING.keys.iter().collect(),
+    };
+
+    /*- Return the claims of the first key that verifies the token -*/
+    for key in candidates {
+        if let Ok(token) = decode::<Claims>(token, &key.decoding, &Validation::new(key.algorithm)) {
+            return Ok(token.claims);
+        };
+    };
+
+    Err(())
+}
@@ -0,0 +1,104 @@
+/*- Passwordless login through links mailed to the user -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ keys, utils, config::CONFIG, user::{ User, generate_suid }, mail::{ MAILER, Mail } };
+use serde::{ Serialize, Deserialize };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const MAGIC_LINK_COLLECTION:&str = "magic_links";
+const MAGIC_LINK_PURPOSE:&str = "magic-link";
+const MAGIC_LINK_LIFETIME:u64 = 60*15;
+
+/*- Structs, enums, unions -*/
+/// # MagicLinkClaims
+/// The claims of the signed token in a login link. Like verification
+/// tokens, the email is included so that the link stops working if
+/// the email changes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct MagicLinkClaims {
+    pub suid    : String,
+    pub email   : String,
+    pub purpose : String,
+    pub jti     : String,
+    pub exp     : usize,
+}
+
+/// # MagicLink
+/// A link which has been mailed. The signature proves a token was
+/// issued by us, this makes sure it's only ever used once.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct MagicLink {
+    pub jti     : String,
+    pub suid    : String,
+    pub expires : u64,
+    pub used    : bool,
+}
+
+/*- Functions -*/
+/*- Create a login token for a user and mail the link to them -*/
+pub(crate) fn send_magic_link(user:&User) -> Result<(), String> {
+    let expires = utils::get_unix_epoch_time() + MAGIC_LINK_LIFETIME;
+    let claims = MagicLinkClaims {
+        suid    : user.suid.clone(),
+        email   : user.email.clone(),
+        purpose : MAGIC_LINK_PURPOSE.to_string(),
+        jti     : generate_suid(),
+        exp     : expires as usize,
+    };
+
+    let collection:Collection<MagicLink> = utils::establish_mclient::<MagicLink>(MAGIC_LINK_COLLECTION);
+    collection.insert_one(MagicLink {
+        jti     : claims.jti.clone(),
+        suid    : user.suid.clone(),
+        expires,
+        used    : false,
+    }, None).map_err(|e| e.to_string())?;
+
+    /*- The link leads to the client, which sends
+        the token to /login/magic-link/exchange -*/
+    let link = format!("{}/login/magic-link?token={}", CONFIG.public_url, keys::sign(&claims));
+
+    MAILER.send(&Mail {
+        to      : user.email.clone(),
+        subject : "Your login link".to_string(),
+        body    : format!(
+            "Hi {},\r\n\r\nOpen the link below to log in.\r\n\r\n{}\r\n\r\nThe link is valid for 15 minutes and can only be used once. If you didn't ask for this, you can ignore this mail.",
+            user.displayname, link
+        ),
+    })
+}
+
+/*- Check a login token and use it up, returning its claims -*/
+pub(crate) fn consume_magic_link(token:&str) -> Result<MagicLinkClaims, ()> {
+    let claims = match keys::verify::<MagicLinkClaims>(token) {
+        Ok(claims) if claims.purpose == MAGIC_LINK_PURPOSE => claims,
+        _ => return Err(()),
+    };
+
+    /*- Atomically mark it as used -*/
+    let collection:Collection<MagicLink> = utils::establish_mclient::<MagicLink>(MAGIC_LINK_COLLECTION);
+    match collection.find_one_and_update(
+        doc!{ "jti": &claims.jti, "suid": &claims.suid, "used": false },
+        doc!{ "$set": { "used": true } },
+        None
+    ) {
+        Ok(Some(_)) => Ok(claims),
+        _ => Err(()),
+    }
+}
+
+/*- Remove every link mailed to a user, used when deleting them -*/
+pub(crate) fn delete_magic_links(suid:&str) -> () {
+    let collection:Collection<MagicLink> = utils::establish_mclient::<MagicLink>(MAGIC_LINK_COLLECTION);
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
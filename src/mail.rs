@@ -0,0 +1,119 @@
+/*- Outgoing mail -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, config::CONFIG, user::generate_suid };
+use lazy_static::lazy_static;
+use std::{ fs, path::PathBuf };
+use lettre::{
+    Message,
+    SmtpTransport,
+    Transport,
+    message::header::ContentType,
+    transport::smtp::authentication::Credentials,
+};
+
+/*- Statics & Constants -*/
+lazy_static! {
+    /*- The transport picked by MAIL_TRANSPORT -*/
+    pub(crate) static ref MAILER:Box<dyn Mailer> = match CONFIG.mail_transport.as_str() {
+        "smtp"   => Box::new(SmtpMailer::from_config()),
+        "outbox" => Box::new(OutboxMailer::new(&CONFIG.mail_outbox_dir)),
+        other    => panic!("Unknown mail transport \"{}\"", other),
+    };
+}
+
+/*- Structs, enums, unions -*/
+/// # Mail
+/// A plain text mail to a single recipient.
+#[derive(Clone, Debug)]
+pub(crate) struct Mail {
+    pub to      : String,
+    pub subject : String,
+    pub body    : String,
+}
+
+/// # Mailer
+/// Something that can deliver mails. Use the `MAILER` static
+/// rather than constructing transports by hand.
+pub(crate) trait Mailer: Send + Sync {
+    fn send(&self, mail:&Mail) -> Result<(), String>;
+}
+
+/// # SmtpMailer
+/// Delivers mails through an SMTP relay.
+pub(crate) struct SmtpMailer {
+    transport: SmtpTransport,
+}
+
+/// # OutboxMailer
+/// Writes every mail to a file in a directory instead of sending
+/// it. Used for local development and tests, where the verification
+/// links etc. can be read straight from the files.
+pub(crate) struct OutboxMailer {
+    dir: PathBuf,
+}
+
+/*- Function implementations -*/
+impl SmtpMailer {
+    pub fn from_config() -> Self {
+        /*- Pick the TLS mode -*/
+        let builder = match CONFIG.smtp_tls.as_str() {
+            "tls"  => SmtpTransport::relay(&CONFIG.smtp_host),
+            "none" => Ok(SmtpTransport::builder_dangerous(&CONFIG.smtp_host)),
+            _      => SmtpTransport::starttls_relay(&CONFIG.smtp_host),
+        }.expect("Failed to set up SMTP transport");
+
+        /*- Credentials are optional -*/
+        let builder = match (&CONFIG.smtp_username, &CONFIG.smtp_password) {
+            (Some(username), Some(password)) => builder.credentials(
+                Credentials::new(username.clone(), password.clone())
+            ),
+            _ => builder,
+        };
+
+        SmtpMailer { transport: builder.port(CONFIG.smtp_port).build() }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail:&Mail) -> Result<(), String> {
+        /*- Build the message -*/
+        let message = Message::builder()
+            .from(CONFIG.mail_from.parse().map_err(|e| format!("Invalid sender: {}", e))?)
+            .to(mail.to.parse().map_err(|e| format!("Invalid recipient: {}", e))?)
+            .subject(mail.subject.clone())
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+            .map_err(|e| e.to_string())?;
+
+        /*- Send it -*/
+        self.transport.send(&message).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+impl OutboxMailer {
+    pub fn new(dir:&str) -> Self {
+        OutboxMailer { dir: PathBuf::from(dir) }
+    }
+}
+
+impl Mailer for OutboxMailer {
+    fn send(&self, mail:&Mail) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+
+        /*- One file per mail, sortable by time -*/
+        let path = self.dir.join(format!("{}-{}.eml", utils::get_unix_epoch_time(), generate_suid()));
+        let contents = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            CONFIG.mail_from, mail.to, mail.subject, mail.body
+        );
+
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+}
@@ -11,6 +11,29 @@ mod utils;
 mod user;
 mod safe_user;
 mod tweet;
+mod password;
+mod config;
+mod keys;
+mod tokens;
+mod mail;
+mod verification;
+mod password_reset;
+mod magic_link;
+mod totp;
+mod throttle;
+mod policy;
+mod deletion;
+mod roles;
+mod sessions;
+mod api_keys;
+mod oauth;
+mod oidc;
+mod audit;
+mod moderation;
+mod invites;
+mod exports;
+mod import;
+mod profile;
 #[path = "resources/dict.rs"] mod dict;
 use fastserve::{ *, RouteRoot as RR, RouteValue as RV, Method };
 use std::ops;
@@ -21,6 +44,16 @@ use std::ops;
 
 /*- Startup -*/
 fn main() -> () {
+    /*- Read the configuration first, so that a bad one stops the server right away -*/
+    lazy_static::initialize(&config::CONFIG);
+
+    /*- `import <file> [--dry-run]` imports accounts instead of serving -*/
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.get(1).map(|e| e.as_str()) == Some("import") {
+        user::migrate_identities();
+        return import::run(&args[2..]);
+    };
+
     /*- The api routes -*/
     let routes:Vec<RR> = vec![
         RR::Stack("/", vec![
@@ -28,13 +61,71 @@ fn main() -> () {
             RR::Endpoint("like",                            RV::Function((Method::Get, api::like          ))),
             RR::Endpoint("tweet",                           RV::Function((Method::Get, api::tweet         ))),
             RR::Endpoint("login",                           RV::Function((Method::Get, api::login         ))),
+            RR::Endpoint("login/2fa",                       RV::Function((Method::Get, api::login_two_factor))),
+            RR::Endpoint("2fa/enroll",                      RV::Function((Method::Get, api::two_factor_enroll))),
+            RR::Endpoint("2fa/confirm",                     RV::Function((Method::Get, api::two_factor_confirm))),
+            RR::Endpoint("2fa/disable",                     RV::Function((Method::Get, api::two_factor_disable))),
+            RR::Endpoint("refresh",                         RV::Function((Method::Get, api::refresh       ))),
+            RR::Endpoint("logout",                          RV::Function((Method::Get, api::logout        ))),
+            RR::Endpoint("sessions",                        RV::Function((Method::Get, api::list_sessions ))),
+            RR::Endpoint("sessions/revoke",                 RV::Function((Method::Get, api::revoke_session))),
+            RR::Endpoint("sessions/revoke-others",          RV::Function((Method::Get, api::revoke_other_sessions))),
+            RR::Endpoint("api-keys",                        RV::Function((Method::Get, api::list_api_keys ))),
+            RR::Endpoint("api-keys/create",                 RV::Function((Method::Get, api::create_api_key))),
+            RR::Endpoint("api-keys/delete",                 RV::Function((Method::Get, api::delete_api_key))),
+            RR::Endpoint("oauth/clients",                   RV::Function((Method::Get, api::oauth_clients ))),
+            RR::Endpoint("oauth/clients/register",          RV::Function((Method::Get, api::oauth_register_client))),
+            RR::Endpoint("oauth/clients/delete",            RV::Function((Method::Get, api::oauth_delete_client))),
+            RR::Endpoint("oauth/authorize",                 RV::Function((Method::Get, api::oauth_authorize))),
+            RR::Endpoint("oauth/consent",                   RV::Function((Method::Get, api::oauth_consent ))),
+            RR::Endpoint("oauth/token",                     RV::Function((Method::Get, api::oauth_token   ))),
+            RR::Endpoint("userinfo",                        RV::Function((Method::Get, api::userinfo      ))),
+            RR::Endpoint("invites",                         RV::Function((Method::Get, api::list_invites  ))),
+            RR::Endpoint("invites/create",                  RV::Function((Method::Get, api::create_invite ))),
+            RR::Endpoint("account/export",                  RV::Function((Method::Get, api::export_data   ))),
+            RR::Endpoint("account/export/status",           RV::Function((Method::Get, api::export_status ))),
+            RR::Endpoint("export/:token",                   RV::Function((Method::Get, api::download_export))),
+            RR::Endpoint("account/delete",                  RV::Function((Method::Get, api::delete_account))),
+            RR::Endpoint("account/deactivate",              RV::Function((Method::Get, api::deactivate_account))),
+            RR::Endpoint("account/update",                  RV::Function((Method::Get, api::update_account))),
+            RR::Endpoint("account/profile",                 RV::Function((Method::Get, api::update_profile))),
+            RR::Endpoint("login/magic-link",                RV::Function((Method::Get, api::request_magic_link))),
+            RR::Endpoint("login/magic-link/exchange",       RV::Function((Method::Get, api::exchange_magic_link))),
+            RR::Endpoint("request-password-reset",          RV::Function((Method::Get, api::request_password_reset))),
+            RR::Endpoint("reset-password",                  RV::Function((Method::Get, api::reset_password))),
             RR::Endpoint("hashtag",                         RV::Function((Method::Get, api::hashtag       ))),
             RR::Endpoint("create-account",                  RV::Function((Method::Get, api::create_account))),
+            RR::Endpoint("verify-email/:token",             RV::Function((Method::Get, api::verify_email  ))),
+            RR::Endpoint("resend-verification",             RV::Function((Method::Get, api::resend_verification))),
             RR::Endpoint("profile_data/:suid",              RV::Function((Method::Get, api::profile_data  ))),
-            RR::Endpoint("profile_image/:profile_image",    RV::Function((Method::Get, api::profile_image ))) 
+            RR::Endpoint("profile_image/:profile_image",    RV::Function((Method::Get, api::profile_image ))),
+            RR::Endpoint("profile_banner/:suid",            RV::Function((Method::Get, api::profile_banner))),
+            RR::Endpoint("admin/lockouts",                  RV::Function((Method::Get, api::admin_lockouts))),
+            RR::Endpoint("admin/lockout",                   RV::Function((Method::Get, api::admin_lockout ))),
+            RR::Endpoint("admin/unlock",                    RV::Function((Method::Get, api::admin_unlock  ))),
+            RR::Endpoint("admin/roles/grant",               RV::Function((Method::Get, api::admin_grant_role))),
+            RR::Endpoint("admin/roles/revoke",              RV::Function((Method::Get, api::admin_revoke_role))),
+            RR::Endpoint("admin/audit",                     RV::Function((Method::Get, api::admin_audit))),
+            RR::Endpoint("admin/suspend",                   RV::Function((Method::Get, api::admin_suspend ))),
+            RR::Endpoint("admin/ban",                       RV::Function((Method::Get, api::admin_ban     ))),
+            RR::Endpoint("admin/reinstate",                 RV::Function((Method::Get, api::admin_reinstate))),
+            RR::Endpoint(".well-known/jwks.json",           RV::Function((Method::Get, api::jwks          ))),
+            RR::Endpoint(".well-known/openid-configuration", RV::Function((Method::Get, api::openid_configuration))),
         ]),
     ];
 
+    /*- Usernames and emails are unique regardless of case -*/
+    user::migrate_identities();
+
+    /*- 2FA secrets stored before they were encrypted -*/
+    totp::encrypt_plaintext_secrets();
+
+    /*- Exports which were being built when the server stopped -*/
+    exports::fail_interrupted_exports();
+
+    /*- Hard-delete accounts whose grace period has passed -*/
+    deletion::spawn_purger();
+
     /*- Start the server -*/
     fastserve::start(fastserve::ServerOptions {
         routes,
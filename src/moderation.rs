@@ -0,0 +1,139 @@
+/*- Suspending, banning and reinstating accounts -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, tokens, roles::{ self, Permission }, user::{ User, get_user } };
+use serde::{ Serialize, Deserialize };
+use mongodb::{
+    bson::{ self, doc },
+    sync::Collection,
+};
+
+/*- Structs, enums, unions -*/
+/// # AccountState
+/// If an account can be used. Suspensions end by themselves once
+/// `until` has passed, bans last until an admin reinstates the
+/// account, and users who deactivate their account reactivate it
+/// by logging in again.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum AccountState {
+    Active,
+    Suspended { until: u64 },
+    Banned,
+    Deactivated,
+}
+
+/*- Function implementations -*/
+impl Default for AccountState {
+    fn default() -> Self { AccountState::Active }
+}
+
+impl AccountState {
+    /*- The state right now, an ended suspension being active -*/
+    pub fn current(&self) -> AccountState {
+        match self {
+            AccountState::Suspended { until } if *until <= utils::get_unix_epoch_time() => AccountState::Active,
+            state => *state,
+        }
+    }
+
+    /*- If the account may log in and be seen by others -*/
+    pub fn is_active(&self) -> bool {
+        self.current() == AccountState::Active
+    }
+}
+
+/*- Functions -*/
+/*- Change the state of an account, logging it out unless it's being
+    reinstated. Returns false if there's no such user -*/
+pub(crate) fn set_state(suid:&str, state:AccountState, reason:Option<&str>) -> Result<bool, ()> {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let result = collection.update_one(
+        doc!{ "suid": suid },
+        doc!{ "$set": {
+            "account_state" : bson::to_bson(&state).map_err(|_| ())?,
+            "state_reason"  : reason,
+        } },
+        None
+    ).map_err(|_| ())?;
+
+    if result.matched_count == 0 { return Ok(false); };
+    if state != AccountState::Active { tokens::revoke_all_for_user(suid); };
+
+    Ok(true)
+}
+
+/*- What it takes to move a user to a new state. Moderators may suspend
+    users and lift suspensions, but only admins may ban, lift bans, or
+    act on users who can moderate accounts themselves -*/
+pub(crate) fn required_permission(user:&User, state:AccountState) -> Permission {
+    let moderates = roles::has_permission(&user.effective_roles(), Permission::ModerateAccounts);
+    match (user.account_state.current(), state) {
+        _ if moderates => Permission::BanAccounts,
+        (_, AccountState::Banned) | (AccountState::Banned, _) => Permission::BanAccounts,
+        _ => Permission::ModerateAccounts,
+    }
+}
+
+/*- If a user may use their tokens and API keys. Deleted
+    users are left to the checks which were there before -*/
+pub(crate) fn is_active(suid:&str) -> bool {
+    get_user(suid).map(|user| user.account_state.is_active()).unwrap_or(true)
+}
+
+/*- The users whose tweets and profiles are hidden. Accounts scheduled
+    for deletion are too, for the grace period before they're deleted -*/
+pub(crate) fn hidden_suids() -> Vec<String> {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let filter = doc!{ "$or": [
+        { "account_state.kind": { "$in": ["banned", "deactivated"] } },
+        { "deletion_scheduled": { "$ne": null } },
+        { "account_state.kind": "suspended", "account_state.until": { "$gt": utils::get_unix_epoch_time() as i64 } },
+    ] };
+
+    match collection.find(filter, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).map(|user| user.suid).collect(),
+        Err(_)     => vec![],
+    }
+}
+
+/*- Tests -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(roles:&[&str], account_state:AccountState) -> User {
+        serde_json::from_value(serde_json::json!({
+            "username": "alice", "displayname": "alice", "password": "",
+            "email": "alice@example.com", "uid": "uid", "suid": "suid",
+            "roles": roles, "account_state": account_state,
+        })).unwrap()
+    }
+
+    #[test]
+    fn moderators_may_suspend_and_lift_suspensions() {
+        let until = utils::get_unix_epoch_time() + 60;
+        assert_eq!(required_permission(&user(&[], AccountState::Active), AccountState::Suspended { until }), Permission::ModerateAccounts);
+        assert_eq!(required_permission(&user(&[], AccountState::Suspended { until }), AccountState::Active), Permission::ModerateAccounts);
+    }
+
+    #[test]
+    fn only_admins_may_ban_or_lift_bans() {
+        assert_eq!(required_permission(&user(&[], AccountState::Active), AccountState::Banned), Permission::BanAccounts);
+        assert_eq!(required_permission(&user(&[], AccountState::Banned), AccountState::Active), Permission::BanAccounts);
+        assert_eq!(required_permission(&user(&[], AccountState::Banned), AccountState::Suspended { until: u64::MAX }), Permission::BanAccounts);
+    }
+
+    #[test]
+    fn only_admins_may_act_on_moderators() {
+        let until = utils::get_unix_epoch_time() + 60;
+        assert_eq!(required_permission(&user(&["moderator"], AccountState::Active), AccountState::Suspended { until }), Permission::BanAccounts);
+        assert_eq!(required_permission(&user(&["admin"], AccountState::Active), AccountState::Suspended { until }), Permission::BanAccounts);
+    }
+}
@@ -0,0 +1,119 @@
+/*- OpenID Connect, on top of the OAuth 2.0 authorization server -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ keys::{ self, KEYRING }, utils, config::CONFIG, user::User, api_keys::Scope };
+use serde::{ Serialize, Deserialize };
+
+/*- Structs, enums, unions -*/
+/// # UserInfo
+/// The standard claims about a user. `sub` is always included, the
+/// rest only if the app was granted the scope they belong to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct UserInfo {
+    pub sub : String,
+
+    /*- profile -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name               : Option<String>,
+
+    /*- email -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email              : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified     : Option<bool>,
+}
+
+/// # IdTokenClaims
+/// Tells an app who signed in. Unlike access tokens, ID tokens
+/// are meant for the app itself, which `aud` is set to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct IdTokenClaims {
+    pub iss : String,
+    pub aud : String,
+    pub exp : usize,
+    pub iat : usize,
+
+    /*- Echoed back from the authorization request, against replays -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce : Option<String>,
+
+    #[serde(flatten)]
+    pub user_info : UserInfo,
+}
+
+/*- Functions -*/
+/*- The claims about a user which the scopes allow -*/
+pub(crate) fn user_info(user:&User, scopes:&[Scope]) -> UserInfo {
+    let profile = scopes.contains(&Scope::Profile);
+    let email   = scopes.contains(&Scope::Email);
+
+    UserInfo {
+        sub                : user.suid.clone(),
+        preferred_username : Some(user.username.clone()).filter(|_| profile),
+        name               : Some(user.displayname.clone()).filter(|_| profile),
+        email              : Some(user.email.clone()).filter(|_| email),
+        email_verified     : Some(user.email_verified).filter(|_| email),
+    }
+}
+
+/*- If ID tokens can be issued, which needs an asymmetric key -*/
+pub(crate) fn is_available() -> bool {
+    KEYRING.id_token_key().is_some()
+}
+
+/*- Create an ID token for an app. None without an asymmetric key -*/
+pub(crate) fn create_id_token(user:&User, client_id:&str, scopes:&[Scope], nonce:Option<String>) -> Option<String> {
+    let key = KEYRING.id_token_key()?;
+    let now = utils::get_unix_epoch_time();
+
+    Some(keys::sign_with(key, &IdTokenClaims {
+        iss       : CONFIG.public_url.clone(),
+        aud       : client_id.to_string(),
+        exp       : (now + CONFIG.access_token_lifetime) as usize,
+        iat       : now as usize,
+        nonce,
+        user_info : user_info(user, scopes),
+    }))
+}
+
+/*- The discovery document, telling apps where everything is.
+    None if OpenID Connect isn't available -*/
+pub(crate) fn discovery_document() -> Option<serde_json::Value> {
+    let issuer = &CONFIG.public_url;
+    let id_token_key = KEYRING.id_token_key()?;
+
+    let mut document = serde_json::json!({
+        "issuer"                                : issuer,
+        "token_endpoint"                        : format!("{}/oauth/token", issuer),
+        "userinfo_endpoint"                     : format!("{}/userinfo", issuer),
+        "jwks_uri"                              : format!("{}/.well-known/jwks.json", issuer),
+        "response_types_supported"              : ["code"],
+        "grant_types_supported"                 : ["authorization_code", "refresh_token"],
+        "subject_types_supported"               : ["public"],
+        "id_token_signing_alg_values_supported" : [format!("{:?}", id_token_key.algorithm)],
+        "code_challenge_methods_supported"      : ["S256"],
+        "scopes_supported"                      : [
+            Scope::OpenId.name(), Scope::Profile.name(), Scope::Email.name(),
+            Scope::TweetWrite.name(), Scope::LikeWrite.name(),
+        ],
+        "claims_supported"                      : [
+            "iss", "sub", "aud", "exp", "iat", "nonce",
+            "preferred_username", "name", "email", "email_verified",
+        ],
+    });
+
+    /*- The consent screen is served by the client, so it's only known if configured -*/
+    if let Some(consent_url) = &CONFIG.oauth_consent_url {
+        document["authorization_endpoint"] = serde_json::json!(consent_url);
+    };
+
+    Some(document)
+}
@@ -0,0 +1,218 @@
+/*- Password hashing -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::utils;
+use lazy_static::lazy_static;
+use rand::rngs::OsRng;
+use argon2::{
+    Argon2,
+    Algorithm,
+    Version,
+    Params,
+    password_hash::{
+        PasswordHash,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString
+    },
+};
+
+/*- Statics & Constants -*/
+/*- Argon2id cost parameters. Memory is in KiB. These
+    follow the OWASP baseline of 19 MiB, 2 passes, 1 lane -*/
+const ARGON2_MEMORY_COST:  u32 = 19456;
+const ARGON2_TIME_COST:    u32 = 2;
+const ARGON2_PARALLELISM:  u32 = 1;
+
+/*- Length of the old unsalted SHA3-256 hex digests -*/
+const LEGACY_HASH_LEN:usize = 64;
+
+lazy_static! {
+    /*- Verified against when an account doesn't exist, so
+        that it takes as long as checking a real password -*/
+    static ref DUMMY_HASH:String = hash_password("dummy password");
+}
+
+/*- Structs, enums, unions -*/
+/// # PasswordCheck
+/// The outcome of checking a password against a stored hash.
+/// `ValidNeedsRehash` means the password was correct, but the
+/// stored hash is either a legacy SHA3 digest or was made with
+/// outdated Argon2 parameters, and should be replaced by
+/// the output of `hash_password`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PasswordCheck {
+    Valid,
+    ValidNeedsRehash,
+    Invalid,
+}
+
+/*- Functions -*/
+/*- The Argon2id hasher with our current parameters -*/
+fn hasher() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(
+            ARGON2_MEMORY_COST,
+            ARGON2_TIME_COST,
+            ARGON2_PARALLELISM,
+            None
+        ).expect("Invalid Argon2 parameters")
+    )
+}
+
+/*- Hash a password into a salted Argon2id PHC string -*/
+pub(crate) fn hash_password(password:&str) -> String {
+    /*- Every password gets its own random salt -*/
+    let salt = SaltString::generate(&mut OsRng);
+
+    /*- Hash and return the PHC string ($argon2id$v=19$...) -*/
+    hasher()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Failed to hash password")
+        .to_string()
+}
+
+/*- Check a password against a stored hash, which
+    may either be a PHC string or a legacy SHA3 digest -*/
+pub(crate) fn verify_password(password:&str, stored:&str) -> PasswordCheck {
+    /*- Legacy hashes are bare hex digests -*/
+    if is_legacy_hash(stored) {
+        return match utils::hash(password) == stored {
+            true  => PasswordCheck::ValidNeedsRehash,
+            false => PasswordCheck::Invalid,
+        };
+    };
+
+    /*- Parse the PHC string -*/
+    let parsed_hash = match PasswordHash::new(stored) {
+        Ok(hash) => hash,
+        Err(_)   => return PasswordCheck::Invalid,
+    };
+
+    /*- The parameters stored in the hash are used for verifying -*/
+    if hasher().verify_password(password.as_bytes(), &parsed_hash).is_err() {
+        return PasswordCheck::Invalid;
+    };
+
+    /*- Correct password, but check if the parameters are outdated -*/
+    match needs_rehash(&parsed_hash) {
+        true  => PasswordCheck::ValidNeedsRehash,
+        false => PasswordCheck::Valid,
+    }
+}
+
+/*- Spend the same time as verify_password would, without any stored hash -*/
+pub(crate) fn verify_dummy_password(password:&str) -> () {
+    verify_password(password, &DUMMY_HASH);
+}
+
+/*- If a hash from elsewhere can be stored as is: any Argon2
+    PHC string, or a legacy SHA3 digest. Both get upgraded to
+    our current parameters on the first login -*/
+pub(crate) fn is_supported_hash(stored:&str) -> bool {
+    if is_legacy_hash(stored) { return true; };
+
+    match PasswordHash::new(stored) {
+        Ok(hash) => [Algorithm::Argon2id, Algorithm::Argon2i, Algorithm::Argon2d]
+            .iter()
+            .any(|algorithm| hash.algorithm == algorithm.ident()),
+        Err(_) => false,
+    }
+}
+
+/*- If the stored hash was made with the old unsalted SHA3 scheme -*/
+fn is_legacy_hash(stored:&str) -> bool {
+    stored.len() == LEGACY_HASH_LEN
+        && stored.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/*- If a (valid) PHC hash differs from our current algorithm or parameters -*/
+fn needs_rehash(hash:&PasswordHash) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() { return true; };
+
+    /*- Compare the cost parameters -*/
+    match Params::try_from(hash) {
+        Ok(params) => params.m_cost() != ARGON2_MEMORY_COST
+                   || params.t_cost() != ARGON2_TIME_COST
+                   || params.p_cost() != ARGON2_PARALLELISM,
+        Err(_) => true,
+    }
+}
+
+/*- Tests -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*- Hash with another Argon2 variant or cost, like imported hashes may be -*/
+    fn hash_with(algorithm:Algorithm, params:Params, password:&str) -> String {
+        Argon2::new(algorithm, Version::V0x13, params)
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string()
+    }
+
+    fn cheap_params() -> Params {
+        Params::new(1024, 1, 1, None).unwrap()
+    }
+
+    #[test]
+    fn verify_password_accepts_current_hashes() {
+        let stored = hash_password("correct horse");
+
+        assert!(stored.starts_with("$argon2id$v=19$"));
+        assert_eq!(verify_password("correct horse", &stored), PasswordCheck::Valid);
+        assert_eq!(verify_password("wrong horse", &stored), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn verify_password_upgrades_legacy_hashes() {
+        let stored = utils::hash("correct horse");
+
+        assert_eq!(verify_password("correct horse", &stored), PasswordCheck::ValidNeedsRehash);
+        assert_eq!(verify_password("wrong horse", &stored), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn verify_password_upgrades_other_argon2_hashes() {
+        for algorithm in [Algorithm::Argon2i, Algorithm::Argon2d, Algorithm::Argon2id] {
+            let stored = hash_with(algorithm, cheap_params(), "correct horse");
+
+            assert_eq!(verify_password("correct horse", &stored), PasswordCheck::ValidNeedsRehash);
+            assert_eq!(verify_password("wrong horse", &stored), PasswordCheck::Invalid);
+        };
+    }
+
+    #[test]
+    fn verify_password_rejects_garbage() {
+        assert_eq!(verify_password("", ""), PasswordCheck::Invalid);
+        assert_eq!(verify_password("correct horse", "correct horse"), PasswordCheck::Invalid);
+        assert_eq!(verify_password("correct horse", "$argon2id$v=19$broken"), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn is_supported_hash_allows_argon2_and_legacy() {
+        assert!(is_supported_hash(&hash_password("correct horse")));
+        assert!(is_supported_hash(&hash_with(Algorithm::Argon2i, cheap_params(), "correct horse")));
+        assert!(is_supported_hash(&hash_with(Algorithm::Argon2d, cheap_params(), "correct horse")));
+        assert!(is_supported_hash(&utils::hash("correct horse")));
+    }
+
+    #[test]
+    fn is_supported_hash_refuses_others() {
+        assert!(!is_supported_hash(""));
+        assert!(!is_supported_hash("correct horse"));
+        assert!(!is_supported_hash(&"g".repeat(LEGACY_HASH_LEN)));
+        assert!(!is_supported_hash(&"a".repeat(LEGACY_HASH_LEN - 1)));
+        assert!(!is_supported_hash("$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW"));
+        assert!(!is_supported_hash("$pbkdf2-sha256$29000$N2ZMqXXOGQOm5xw$2JQ7Sb3u1xBOG9bQTKpl3UK7f4zXM6PHRxWjjwPd1vE"));
+    }
+}
@@ -0,0 +1,93 @@
+/*- Password resets -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, config::CONFIG, user::User, mail::{ MAILER, Mail } };
+use serde::{ Serialize, Deserialize };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const PASSWORD_RESET_COLLECTION:&str = "password_resets";
+const PASSWORD_RESET_LIFETIME:u64 = 60*60;
+
+/*- Structs, enums, unions -*/
+/// # PasswordReset
+/// A pending password reset. Like refresh tokens, only the hash
+/// of the token is stored, and every token can only be used once.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct PasswordReset {
+    pub token_hash : String,
+    pub suid       : String,
+    pub expires    : u64,
+    pub used       : bool,
+}
+
+/*- Functions -*/
+/*- Create a reset token for a user and mail it to them -*/
+pub(crate) fn send_password_reset(user:&User) -> Result<(), String> {
+    let token = utils::generate_token();
+    let collection:Collection<PasswordReset> = utils::establish_mclient::<PasswordReset>(PASSWORD_RESET_COLLECTION);
+
+    /*- Only the latest requested token is valid -*/
+    collection.delete_many(doc!{ "suid": &user.suid }, None).ok();
+    collection.insert_one(PasswordReset {
+        token_hash : utils::hash(&token),
+        suid       : user.suid.clone(),
+        expires    : utils::get_unix_epoch_time() + PASSWORD_RESET_LIFETIME,
+        used       : false,
+    }, None).map_err(|e| e.to_string())?;
+
+    /*- The link leads to the client, which sends the
+        token and the new password to /reset-password -*/
+    let link = format!("{}/reset-password?token={}", CONFIG.public_url, token);
+
+    MAILER.send(&Mail {
+        to      : user.email.clone(),
+        subject : "Reset your password".to_string(),
+        body    : format!(
+            "Hi {},\r\n\r\nSomeone asked to reset the password of your account. If it was you, open the link below.\r\n\r\n{}\r\n\r\nThe link is valid for one hour. If you didn't ask for this, you can ignore this mail.",
+            user.displayname, link
+        ),
+    })
+}
+
+/*- Check a reset token without using it up, returning the suid of the user it belongs to -*/
+pub(crate) fn find_password_reset(token:&str) -> Result<String, ()> {
+    let collection:Collection<PasswordReset> = utils::establish_mclient::<PasswordReset>(PASSWORD_RESET_COLLECTION);
+
+    match collection.find_one(doc!{ "token_hash": utils::hash(token), "used": false }, None) {
+        Ok(Some(reset)) if reset.expires >= utils::get_unix_epoch_time() => Ok(reset.suid),
+        _ => Err(()),
+    }
+}
+
+/*- Use up a reset token, returning the suid of the user it belongs to -*/
+pub(crate) fn consume_password_reset(token:&str) -> Result<String, ()> {
+    let collection:Collection<PasswordReset> = utils::establish_mclient::<PasswordReset>(PASSWORD_RESET_COLLECTION);
+
+    /*- Atomically mark it as used -*/
+    let reset = collection.find_one_and_update(
+        doc!{ "token_hash": utils::hash(token), "used": false },
+        doc!{ "$set": { "used": true } },
+        None
+    );
+
+    match reset {
+        Ok(Some(reset)) if reset.expires >= utils::get_unix_epoch_time() => Ok(reset.suid),
+        _ => Err(()),
+    }
+}
+
+/*- Remove every pending reset of a user, used when deleting them -*/
+pub(crate) fn delete_password_resets(suid:&str) -> () {
+    let collection:Collection<PasswordReset> = utils::establish_mclient::<PasswordReset>(PASSWORD_RESET_COLLECTION);
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
@@ -0,0 +1,267 @@
+/*- Credential policy -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ config::CONFIG, dict::DICTIONARY };
+use regex::Regex;
+use url::Url;
+use chrono::{ NaiveDate, Datelike };
+
+/*- Structs, enums, unions -*/
+/// # PolicyViolation
+/// Why a username, password, date of birth or profile field was rejected.
+/// Use `message()` to get what to respond with.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PolicyViolation {
+    PasswordTooShort(usize),
+    PasswordTooLong(usize),
+    PasswordTooWeak,
+    InvalidUsername,
+    InvalidDateOfBirth,
+    TooYoung(u64),
+    FieldTooLong(&'static str, usize),
+    InvalidWebsite,
+    InvalidBanner,
+}
+
+/*- Function implementations -*/
+impl PolicyViolation {
+    /*- The dictionary message, with the limits filled in -*/
+    pub fn message(&self) -> String {
+        match self {
+            PolicyViolation::PasswordTooShort(min) => DICTIONARY.error.password.len_min.replace("{}", &min.to_string()),
+            PolicyViolation::PasswordTooLong(max)  => DICTIONARY.error.password.len_max.replace("{}", &max.to_string()),
+            PolicyViolation::PasswordTooWeak       => DICTIONARY.error.password.weak.to_string(),
+            PolicyViolation::InvalidUsername       => DICTIONARY.error.invalid.username.to_string(),
+            PolicyViolation::InvalidDateOfBirth    => DICTIONARY.error.invalid.date_of_birth.to_string(),
+            PolicyViolation::TooYoung(min)         => DICTIONARY.error.too_young.replace("{}", &min.to_string()),
+            PolicyViolation::FieldTooLong(field, max) => DICTIONARY.error.too_long.replacen("{}", field, 1).replacen("{}", &max.to_string(), 1),
+            PolicyViolation::InvalidWebsite        => DICTIONARY.error.invalid.website.to_string(),
+            PolicyViolation::InvalidBanner         => DICTIONARY.error.invalid.banner.to_string(),
+        }
+    }
+}
+
+/*- Functions -*/
+/*- Check a new password. The username and email are
+    passed so that passwords containing them score lower -*/
+pub(crate) fn check_password(password:&str, username:&str, email:&str) -> Result<(), PolicyViolation> {
+    let length = password.chars().count();
+
+    /*- Length -*/
+    if length < CONFIG.password_min_length { return Err(PolicyViolation::PasswordTooShort(CONFIG.password_min_length)); };
+    if length >= CONFIG.password_max_length { return Err(PolicyViolation::PasswordTooLong(CONFIG.password_max_length)); };
+
+    /*- Strength, if required -*/
+    if password_strength(password, username, email) < CONFIG.password_min_strength {
+        return Err(PolicyViolation::PasswordTooWeak);
+    };
+
+    Ok(())
+}
+
+/*- Check a new username -*/
+pub(crate) fn check_username(username:&str) -> Result<(), PolicyViolation> {
+    let length = username.chars().count();
+
+    /*- Length -*/
+    if length < CONFIG.username_min_length || length > CONFIG.username_max_length {
+        return Err(PolicyViolation::InvalidUsername);
+    };
+
+    /*- "@" tells emails apart from usernames when logging in -*/
+    if username.contains('@') {
+        return Err(PolicyViolation::InvalidUsername);
+    };
+
+    /*- Allowed characters -*/
+    let charset = Regex::new(&format!("^[{}]+$", CONFIG.username_charset)).expect("Invalid USERNAME_CHARSET");
+    if !charset.is_match(username) {
+        return Err(PolicyViolation::InvalidUsername);
+    };
+
+    /*- Reserved names -*/
+    if CONFIG.reserved_usernames.iter().any(|reserved| reserved.eq_ignore_ascii_case(username)) {
+        return Err(PolicyViolation::InvalidUsername);
+    };
+
+    Ok(())
+}
+
+/*- Check a date of birth (YYYY-MM-DD) against the minimum age. It's
+    optional, unless there is a minimum age to check it against. An
+    empty one counts as none, which is how it's removed again -*/
+pub(crate) fn check_date_of_birth(date_of_birth:Option<&str>) -> Result<Option<NaiveDate>, PolicyViolation> {
+    let date_of_birth = match date_of_birth.map(|e| e.trim()).filter(|e| !e.is_empty()) {
+        Some(date_of_birth) => NaiveDate::parse_from_str(date_of_birth, "%Y-%m-%d")
+            .map_err(|_| PolicyViolation::InvalidDateOfBirth)?,
+        None if CONFIG.minimum_age == 0 => return Ok(None),
+        None => return Err(PolicyViolation::TooYoung(CONFIG.minimum_age)),
+    };
+
+    /*- Nobody is born in the future -*/
+    let today = chrono::Utc::now().naive_utc().date();
+    if date_of_birth > today { return Err(PolicyViolation::InvalidDateOfBirth); };
+
+    if (age(date_of_birth, today) as u64) < CONFIG.minimum_age {
+        return Err(PolicyViolation::TooYoung(CONFIG.minimum_age));
+    };
+
+    Ok(Some(date_of_birth))
+}
+
+/*- Someone's age in whole years on a given day -*/
+pub(crate) fn age(date_of_birth:NaiveDate, on:NaiveDate) -> u32 {
+    let had_birthday = (on.month(), on.day()) >= (date_of_birth.month(), date_of_birth.day());
+    (on.year() - date_of_birth.year() - if had_birthday { 0 } else { 1 }).max(0) as u32
+}
+
+/*- Check a free text profile field, like the bio. Returns
+    None if it's empty, which removes it from the profile -*/
+pub(crate) fn check_profile_text(field:&'static str, value:&str, max_length:usize) -> Result<Option<String>, PolicyViolation> {
+    let value = value.trim();
+    if value.chars().count() > max_length { return Err(PolicyViolation::FieldTooLong(field, max_length)); };
+
+    Ok(Some(value.to_string()).filter(|value| !value.is_empty()))
+}
+
+/*- Check a website link. Only http(s) URLs with a host are
+    allowed, so that profiles can't link to javascript: and such -*/
+pub(crate) fn check_website(website:&str, max_length:usize) -> Result<Option<String>, PolicyViolation> {
+    let website = match check_profile_text("Website", website, max_length)? {
+        Some(website) => website,
+        None => return Ok(None),
+    };
+
+    match Url::parse(&website) {
+        Ok(url) if (url.scheme() == "http" || url.scheme() == "https") && url.host_str().is_some() => Ok(Some(url.to_string())),
+        _ => Err(PolicyViolation::InvalidWebsite),
+    }
+}
+
+/*- Score a password from 0 (very weak) to 4 (strong).
+    A rough estimate based on length and character variety -*/
+pub(crate) fn password_strength(password:&str, username:&str, email:&str) -> u8 {
+    let length = password.chars().count();
+    let lowercase = password.to_lowercase();
+
+    /*- Passwords containing the username or email name are guessable -*/
+    let email_name = email.split('@').next().unwrap_or("").to_lowercase();
+    if (!username.is_empty() && lowercase.contains(&username.to_lowercase()))
+    || (email_name.len() >= 3 && lowercase.contains(&email_name)) {
+        return 0;
+    };
+
+    /*- Character classes used -*/
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ].iter().filter(|used| **used).count();
+
+    /*- Repeating a few characters doesn't make a password stronger -*/
+    let mut unique = password.chars().collect::<Vec<char>>();
+    unique.sort_unstable();
+    unique.dedup();
+    if unique.len() < 4 { return 0; };
+
+    /*- Long passphrases are strong regardless of variety -*/
+    match (length, classes) {
+        (20.., _)       => 4,
+        (14.., 3..)     => 4,
+        (14.., _)       => 3,
+        (10.., 3..)     => 3,
+        (10.., _)       => 2,
+        (8.., 3..)      => 2,
+        (8.., _)        => 1,
+        _               => 0,
+    }
+}
+
+/*- Tests, which assume the default configuration -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_strength_scores_length_and_variety() {
+        assert_eq!(password_strength("abc", "", ""), 0);
+        assert_eq!(password_strength("abcdefgh", "", ""), 1);
+        assert_eq!(password_strength("abcdef1!", "", ""), 2);
+        assert_eq!(password_strength("abcdefghij", "", ""), 2);
+        assert_eq!(password_strength("Abcdefgh1!", "", ""), 3);
+        assert_eq!(password_strength("abcdefghijklmn", "", ""), 3);
+        assert_eq!(password_strength("Abcdefghijkl1!", "", ""), 4);
+        assert_eq!(password_strength("correct horse battery staple", "", ""), 4);
+    }
+
+    #[test]
+    fn password_strength_rejects_repetition_and_identity() {
+        assert_eq!(password_strength("aaaaaaaaaaaaaaaaaaaaaaaa", "", ""), 0);
+        assert_eq!(password_strength("abababababababababababab", "", ""), 0);
+        assert_eq!(password_strength("MyNameIsAlice!2024", "alice", ""), 0);
+        assert_eq!(password_strength("bobsmith-Secret-42", "", "BobSmith@example.com"), 0);
+
+        /*- Short email names are too common to count -*/
+        assert_eq!(password_strength("Abcdefghijkl1!", "", "ab@example.com"), 4);
+    }
+
+    #[test]
+    fn check_username_accepts_valid_names() {
+        assert_eq!(check_username("alice"), Ok(()));
+        assert_eq!(check_username("bob_smith.92"), Ok(()));
+    }
+
+    #[test]
+    fn check_username_rejects_invalid_names() {
+        assert_eq!(check_username("al"), Err(PolicyViolation::InvalidUsername));
+        assert_eq!(check_username(&"a".repeat(21)), Err(PolicyViolation::InvalidUsername));
+        assert_eq!(check_username("alice@example.com"), Err(PolicyViolation::InvalidUsername));
+        assert_eq!(check_username("alice smith"), Err(PolicyViolation::InvalidUsername));
+        assert_eq!(check_username("ålice"), Err(PolicyViolation::InvalidUsername));
+        assert_eq!(check_username("Admin"), Err(PolicyViolation::InvalidUsername));
+    }
+
+    fn date(year:i32, month:u32, day:u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn age_counts_whole_years() {
+        assert_eq!(age(date(1990, 6, 15), date(2020, 6, 14)), 29);
+        assert_eq!(age(date(1990, 6, 15), date(2020, 6, 15)), 30);
+        assert_eq!(age(date(1990, 6, 15), date(1990, 6, 15)), 0);
+        assert_eq!(age(date(1990, 6, 15), date(1980, 1, 1)), 0);
+    }
+
+    #[test]
+    fn age_of_leap_day_births() {
+        /*- Outside leap years, the birthday is on March 1st -*/
+        assert_eq!(age(date(2000, 2, 29), date(2001, 2, 28)), 0);
+        assert_eq!(age(date(2000, 2, 29), date(2001, 3, 1)), 1);
+        assert_eq!(age(date(2000, 2, 29), date(2004, 2, 28)), 3);
+        assert_eq!(age(date(2000, 2, 29), date(2004, 2, 29)), 4);
+    }
+
+    #[test]
+    fn check_date_of_birth_is_optional_without_minimum_age() {
+        assert_eq!(check_date_of_birth(None), Ok(None));
+        assert_eq!(check_date_of_birth(Some("")), Ok(None));
+        assert_eq!(check_date_of_birth(Some("  ")), Ok(None));
+    }
+
+    #[test]
+    fn check_date_of_birth_parses_dates() {
+        assert_eq!(check_date_of_birth(Some("2000-02-29")), Ok(Some(date(2000, 2, 29))));
+        assert_eq!(check_date_of_birth(Some(" 1990-06-15 ")), Ok(Some(date(1990, 6, 15))));
+        assert_eq!(check_date_of_birth(Some("2001-02-29")), Err(PolicyViolation::InvalidDateOfBirth));
+        assert_eq!(check_date_of_birth(Some("15/06/1990")), Err(PolicyViolation::InvalidDateOfBirth));
+        assert_eq!(check_date_of_birth(Some("9999-01-01")), Err(PolicyViolation::InvalidDateOfBirth));
+    }
+}
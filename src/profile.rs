@@ -0,0 +1,83 @@
+/*- Public profile fields and banner images -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::policy::{ self, PolicyViolation };
+use image::{ ImageFormat, RgbImage, imageops::FilterType };
+use std::fs;
+
+/*- Statics & Constants -*/
+pub(crate) const BIO_MAX_LENGTH:usize = 280;
+pub(crate) const LOCATION_MAX_LENGTH:usize = 64;
+pub(crate) const PRONOUNS_MAX_LENGTH:usize = 32;
+pub(crate) const WEBSITE_MAX_LENGTH:usize = 256;
+
+/*- Banners are cropped to this size, and may be uploaded at most this large -*/
+const BANNER_DIR:&str = "uploads/banners";
+const BANNER_WIDTH:u32 = 1500;
+const BANNER_HEIGHT:u32 = 500;
+const BANNER_MAX_SIZE:usize = 1024*1024*2;
+
+/*- Functions -*/
+/*- Check the fields sent to /account/profile. An empty value
+    removes a field, one which isn't sent is left as it is -*/
+pub(crate) fn check_bio(bio:&str) -> Result<Option<String>, PolicyViolation> {
+    policy::check_profile_text("Bio", bio, BIO_MAX_LENGTH)
+}
+pub(crate) fn check_location(location:&str) -> Result<Option<String>, PolicyViolation> {
+    policy::check_profile_text("Location", location, LOCATION_MAX_LENGTH)
+}
+pub(crate) fn check_pronouns(pronouns:&str) -> Result<Option<String>, PolicyViolation> {
+    policy::check_profile_text("Pronouns", pronouns, PRONOUNS_MAX_LENGTH)
+}
+pub(crate) fn check_website(website:&str) -> Result<Option<String>, PolicyViolation> {
+    policy::check_website(website, WEBSITE_MAX_LENGTH)
+}
+
+/*- Where a user's banner is stored -*/
+pub(crate) fn banner_path(suid:&str) -> String {
+    format!("{}/{}.jpg", BANNER_DIR, suid)
+}
+
+/*- Where a user's banner is served from -*/
+pub(crate) fn banner_url(suid:&str) -> String {
+    format!("/profile_banner/{}", suid)
+}
+
+/*- Decode a base64 encoded JPEG or PNG banner, cropped to size. It's
+    re-encoded when stored, so that only real images are kept -*/
+pub(crate) fn decode_banner(encoded:&str) -> Result<RgbImage, PolicyViolation> {
+    let bytes = base64::decode(encoded.trim()).map_err(|_| PolicyViolation::InvalidBanner)?;
+    if bytes.len() > BANNER_MAX_SIZE { return Err(PolicyViolation::InvalidBanner); };
+
+    let banner = match image::guess_format(&bytes) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png)) =>
+            image::load_from_memory_with_format(&bytes, format).map_err(|_| PolicyViolation::InvalidBanner)?,
+        _ => return Err(PolicyViolation::InvalidBanner),
+    };
+
+    Ok(banner.resize_to_fill(BANNER_WIDTH, BANNER_HEIGHT, FilterType::Triangle).to_rgb8())
+}
+
+/*- Store a decoded banner, as a JPEG like profile images. It's written
+    next to the old one first, so that one stays intact if writing fails -*/
+pub(crate) fn save_banner(suid:&str, banner:&RgbImage) -> Result<(), String> {
+    fs::create_dir_all(BANNER_DIR).map_err(|e| e.to_string())?;
+
+    let temporary = format!("{}.tmp", banner_path(suid));
+    banner.save_with_format(&temporary, ImageFormat::Jpeg).map_err(|e| e.to_string())?;
+    fs::rename(&temporary, banner_path(suid)).map_err(|e| {
+        fs::remove_file(&temporary).ok();
+        e.to_string()
+    })
+}
+
+/*- Remove a user's banner, which may not exist -*/
+pub(crate) fn delete_banner(suid:&str) -> () {
+    fs::remove_file(banner_path(suid)).ok();
+}
@@ -24,7 +24,13 @@ pub(crate) fn get_error_code(code:u8) -> String {
 /*- A dictionary of phrases that are ex
     responded with inside of this project -*/
 pub struct Dictionary<'lf> {
-    pub error:Error<'lf>
+    pub error:Error<'lf>,
+    pub success:Success<'lf>,
+}
+
+/*- (OK) Messages for successful requests -*/
+pub struct Success<'lf> {
+    pub email_verified:&'lf str,
 }
 
 /*- (ERR) Error messages -*/
@@ -32,14 +38,29 @@ pub struct Error<'lf> {
     pub in_use: InUse<'lf>,
     pub password: Password<'lf>,
     pub invalid: Invalid<'lf>,
+    pub two_factor: TwoFactor<'lf>,
     pub login:&'lf str,
     pub unauthorized:&'lf str,
+    pub unverified:&'lf str,
+    pub throttled:&'lf str,
+    pub forbidden:&'lf str,
+    pub mail:&'lf str,
+    pub scope:&'lf str,
+    pub delegated:&'lf str,
+    pub suspended:&'lf str,
+    pub banned:&'lf str,
+    pub invite_quota:&'lf str,
+    pub export_pending:&'lf str,
+    pub too_young:&'lf str,
+    pub too_long:&'lf str,
+    pub openid_unavailable:&'lf str,
 }
 
 /*- (ERR) When something with the password has gone wrong -*/
 pub struct Password<'lf> {
     pub len_min: &'lf str,
     pub len_max: &'lf str,
+    pub weak: &'lf str,
 }
 
 /*- (ERR) When some parameters are already in use -*/
@@ -48,10 +69,32 @@ pub struct InUse<'lf> {
     pub username:&'lf str
 }
 
+/*- (ERR) When something with 2FA has gone wrong -*/
+pub struct TwoFactor<'lf> {
+    pub code:&'lf str,
+    pub enabled:&'lf str,
+    pub not_enrolled:&'lf str,
+    pub unavailable:&'lf str,
+}
+
 /*- (ERR) When some parameters are invalid -*/
 pub struct Invalid<'lf> {
     pub email:&'lf str,
-    pub username:&'lf str
+    pub username:&'lf str,
+    pub verification:&'lf str,
+    pub password_reset:&'lf str,
+    pub magic_link:&'lf str,
+    pub invite:&'lf str,
+    pub password_hash:&'lf str,
+    pub date_of_birth:&'lf str,
+    pub website:&'lf str,
+    pub banner:&'lf str,
+    pub role:&'lf str,
+    pub scope:&'lf str,
+    pub client:&'lf str,
+    pub redirect_uri:&'lf str,
+    pub code_challenge:&'lf str,
+    pub event:&'lf str,
 }
 
 /*- Create the dictionary -*/
@@ -64,12 +107,49 @@ pub(crate) const DICTIONARY:Dictionary = Dictionary {
         password: Password {
             len_min: "Password must be atleast {} characters long",
             len_max: "Password must be less than {} characters long",
+            weak: "Password is too weak",
         },
         invalid: Invalid {
             email: "Email is invalid",
-            username: "Username is invalid"
+            username: "Username is invalid",
+            verification: "Verification link is invalid or has expired.",
+            password_reset: "Password reset link is invalid or has expired.",
+            magic_link: "Login link is invalid, expired or has already been used.",
+            invite: "Invite code is invalid, expired or has already been used.",
+            password_hash: "Password hash format is not supported",
+            date_of_birth: "Date of birth is invalid, it must be written like 2000-01-31",
+            website: "Website must be a http or https link",
+            banner: "Banner must be a base64 encoded JPEG or PNG image of at most 2 MB",
+            role: "Role is invalid",
+            scope: "Scope is invalid",
+            client: "Client is invalid",
+            redirect_uri: "Redirect URI is invalid",
+            code_challenge: "A S256 code challenge is required",
+            event: "Event is invalid"
+        },
+        two_factor: TwoFactor {
+            code: "Two-factor code is invalid.",
+            enabled: "Two-factor authentication is already enabled.",
+            not_enrolled: "Two-factor authentication has not been set up.",
+            unavailable: "Two-factor authentication is not available, no TOTP_ENCRYPTION_KEY is configured."
         },
-        login: "Email or password is incorrect.",
-        unauthorized: "Unauthorized."
+        login: "Username, email or password is incorrect.",
+        unauthorized: "Unauthorized.",
+        unverified: "Email address has not been verified.",
+        throttled: "Too many failed login attempts. Try again in {} seconds.",
+        forbidden: "Forbidden.",
+        mail: "Failed to send email.",
+        scope: "Token is missing the {} scope.",
+        delegated: "API keys and third-party apps can't be used for this.",
+        suspended: "Account is suspended until {}.",
+        banned: "Account is banned.",
+        invite_quota: "You can't create any more invite codes.",
+        export_pending: "An export is already being prepared.",
+        too_young: "You must be at least {} years old to sign up.",
+        too_long: "{} must be at most {} characters long",
+        openid_unavailable: "The openid scope is not available, no asymmetric signing key is configured."
+    },
+    success: Success {
+        email_verified: "Email address verified."
     }
 };
\ No newline at end of file
@@ -0,0 +1,100 @@
+/*- Roles & permissions -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use serde::{ Serialize, Deserialize };
+use std::str::FromStr;
+
+/*- Structs, enums, unions -*/
+/// # Role
+/// What a user is allowed to do is decided by their roles. Every
+/// user has the `User` role, the others are granted by admins.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+/// # Permission
+/// Something an endpoint can require. Endpoints check permissions
+/// rather than roles, so that roles can change without touching them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Permission {
+    ManageRoles,
+    ManageLockouts,
+    ViewAuditLog,
+    ModerateAccounts,
+    BanAccounts,
+    CreateInvites,
+}
+
+/*- Function implementations -*/
+impl Role {
+    /*- The permissions a role grants -*/
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::User      => &[],
+            Role::Moderator => &[Permission::ModerateAccounts],
+            Role::Admin     => &[Permission::ManageRoles, Permission::ManageLockouts, Permission::ViewAuditLog, Permission::ModerateAccounts, Permission::BanAccounts, Permission::CreateInvites],
+        }
+    }
+}
+
+/*- Parse roles sent in headers -*/
+impl FromStr for Role {
+    type Err = ();
+    fn from_str(value:&str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "user"      => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin"     => Ok(Role::Admin),
+            _           => Err(()),
+        }
+    }
+}
+
+/*- Functions -*/
+/*- If any of the roles grants a permission -*/
+pub(crate) fn has_permission(roles:&[Role], permission:Permission) -> bool {
+    roles.iter().any(|role| role.permissions().contains(&permission))
+}
+
+/*- Tests -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_have_no_permissions() {
+        assert!(Role::User.permissions().is_empty());
+    }
+
+    #[test]
+    fn moderators_may_only_moderate_accounts() {
+        assert!(has_permission(&[Role::User, Role::Moderator], Permission::ModerateAccounts));
+        for permission in [Permission::ManageRoles, Permission::ManageLockouts, Permission::ViewAuditLog, Permission::BanAccounts, Permission::CreateInvites] {
+            assert!(!has_permission(&[Role::User, Role::Moderator], permission));
+        };
+    }
+
+    #[test]
+    fn admins_have_every_permission() {
+        for permission in [Permission::ManageRoles, Permission::ManageLockouts, Permission::ViewAuditLog, Permission::ModerateAccounts, Permission::BanAccounts, Permission::CreateInvites] {
+            assert!(has_permission(&[Role::User, Role::Admin], permission));
+        };
+    }
+
+    #[test]
+    fn roles_parse_from_headers() {
+        assert_eq!(" Moderator ".parse::<Role>(), Ok(Role::Moderator));
+        assert_eq!("admin".parse::<Role>(), Ok(Role::Admin));
+        assert_eq!("owner".parse::<Role>(), Err(()));
+    }
+}
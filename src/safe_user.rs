@@ -14,7 +14,28 @@ pub(crate) struct SafeUser {
     pub username    : String,
     pub displayname : String,
     pub suid        : String,
-    pub age         : u8,
+
+    /*- Only if the user has chosen to show it -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age         : Option<u32>,
+
+    /*- Profile fields, left out if the user hasn't filled them in -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio         : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location    : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website     : Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pronouns    : Option<String>,
+
+    /*- Where the banner image is served from -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner      : Option<String>,
+
+    /*- When the account was created, if known -*/
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub joined      : Option<u64>,
 }
 
 /*- For printing / debugging -*/
@@ -22,7 +43,7 @@ impl fmt::Debug for SafeUser {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "User {{ username: {}, displayname: {}, age: {}, suid: {} }}",
+            "User {{ username: {}, displayname: {}, age: {:?}, suid: {} }}",
             self.username, self.displayname, self.age, self.suid
         )
     }
@@ -30,10 +51,5 @@ impl fmt::Debug for SafeUser {
 
 /*- Convert user to SafeUser -*/
 pub(crate) fn convert_user(user: User) -> SafeUser {
-    SafeUser {
-        username    : user.username,
-        displayname : user.displayname,
-        suid        : user.suid,
-        age         : user.age,
-    }
+    User::to_safe(user)
 }
\ No newline at end of file
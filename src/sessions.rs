@@ -0,0 +1,186 @@
+/*- Sessions (logged in devices) -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, tokens, user::generate_suid, api_keys::Scope };
+use serde::{ Serialize, Deserialize };
+use std::collections::HashMap;
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+};
+
+/*- Statics & Constants -*/
+const SESSION_COLLECTION:&str = "sessions";
+
+/*- last_seen is only updated if it's older than this,
+    so that not every request causes a write -*/
+const LAST_SEEN_RESOLUTION:u64 = 60;
+
+/*- Structs, enums, unions -*/
+/// # Session
+/// One login on one device. Access tokens carry the `sid` in their
+/// claims, and the refresh token family of the login uses it as its
+/// `family`, so revoking a session stops both from working.
+/// Sessions started by third-party apps name the app and
+/// the scopes the user consented to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Session {
+    pub sid        : String,
+    pub suid       : String,
+    pub user_agent : String,
+    pub ip         : String,
+    pub created    : u64,
+    pub last_seen  : u64,
+    pub revoked    : bool,
+
+    #[serde(default)]
+    pub client_id  : Option<String>,
+    #[serde(default)]
+    pub scopes     : Option<Vec<Scope>>,
+}
+
+/*- Functions -*/
+/*- Record a new login, returning its session id -*/
+pub(crate) fn start_session(suid:&str, user_agent:&str, ip:&str) -> String {
+    insert_session(suid, user_agent, ip, None, None)
+}
+
+/*- Record a third-party app being given access, returning the session id -*/
+pub(crate) fn start_client_session(suid:&str, user_agent:&str, ip:&str, client_id:&str, scopes:Vec<Scope>) -> String {
+    insert_session(suid, user_agent, ip, Some(client_id.to_string()), Some(scopes))
+}
+
+fn insert_session(suid:&str, user_agent:&str, ip:&str, client_id:Option<String>, scopes:Option<Vec<Scope>>) -> String {
+    let sid = generate_suid();
+    let now = utils::get_unix_epoch_time();
+
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    collection.insert_one(Session {
+        sid        : sid.clone(),
+        suid       : suid.to_string(),
+        user_agent : user_agent.to_string(),
+        ip         : ip.to_string(),
+        created    : now,
+        last_seen  : now,
+        revoked    : false,
+        client_id,
+        scopes,
+    }, None).ok();
+
+    sid
+}
+
+/*- Get a session by its id -*/
+pub(crate) fn get(sid:&str) -> Option<Session> {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    collection.find_one(doc!{ "sid": sid }, None).ok().flatten()
+}
+
+/*- Check that a session hasn't been revoked, and mark it as seen -*/
+pub(crate) fn check_and_touch(sid:&str) -> bool {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    let now = utils::get_unix_epoch_time();
+
+    let active = match collection.find_one(doc!{ "sid": sid }, None) {
+        Ok(Some(session)) => !session.revoked,
+        _ => false,
+    };
+
+    if active {
+        collection.update_one(
+            doc!{ "sid": sid, "last_seen": { "$lt": (now - LAST_SEEN_RESOLUTION) as i64 } },
+            doc!{ "$set": { "last_seen": now as i64 } },
+            None
+        ).ok();
+    };
+
+    active
+}
+
+/*- The device a request came from -*/
+pub(crate) fn user_agent(headers:&HashMap<String, String>) -> String {
+    headers.get("user-agent")
+        .or_else(|| headers.get("User-Agent"))
+        .map(|e| e.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/*- Every active session of a user, most recently seen first -*/
+pub(crate) fn list(suid:&str) -> Vec<Session> {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    let mut sessions = match collection.find(doc!{ "suid": suid, "revoked": false }, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect::<Vec<Session>>(),
+        Err(_)     => vec![],
+    };
+
+    sessions.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    sessions
+}
+
+/*- Every session of a user, including revoked ones, for exporting their data -*/
+pub(crate) fn all(suid:&str) -> Vec<Session> {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    match collection.find(doc!{ "suid": suid }, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect(),
+        Err(_)     => vec![],
+    }
+}
+
+/*- Revoke one of a user's sessions. Returns false if they have no such session -*/
+pub(crate) fn revoke(suid:&str, sid:&str) -> bool {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    let revoked = match collection.update_one(
+        doc!{ "suid": suid, "sid": sid },
+        doc!{ "$set": { "revoked": true } },
+        None
+    ) {
+        Ok(result) => result.matched_count > 0,
+        Err(_)     => false,
+    };
+
+    /*- The session's refresh tokens -*/
+    if revoked { tokens::revoke_refresh_family(sid); };
+    revoked
+}
+
+/*- Revoke every session of a user except one -*/
+pub(crate) fn revoke_others(suid:&str, keep_sid:&str) -> () {
+    for session in list(suid).iter().filter(|session| session.sid != keep_sid) {
+        revoke(suid, &session.sid);
+    };
+}
+
+/*- Revoke every session of a third-party app, in every account -*/
+pub(crate) fn revoke_client(client_id:&str) -> () {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    let sessions = match collection.find(doc!{ "client_id": client_id, "revoked": false }, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect::<Vec<Session>>(),
+        Err(_)     => return,
+    };
+
+    for session in sessions {
+        revoke(&session.suid, &session.sid);
+    };
+}
+
+/*- Revoke every session of a user -*/
+pub(crate) fn revoke_all(suid:&str) -> () {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    collection.update_many(
+        doc!{ "suid": suid },
+        doc!{ "$set": { "revoked": true } },
+        None
+    ).ok();
+}
+
+/*- Remove every session of a user, used when deleting them -*/
+pub(crate) fn delete_sessions(suid:&str) -> () {
+    let collection:Collection<Session> = utils::establish_mclient::<Session>(SESSION_COLLECTION);
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
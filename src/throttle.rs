@@ -0,0 +1,152 @@
+/*- Login throttling -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, user::normalize_identifier };
+use serde::{ Serialize, Deserialize };
+use std::net::TcpStream;
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+    options::{ FindOneAndUpdateOptions, ReturnDocument },
+};
+
+/*- Statics & Constants -*/
+const LOGIN_ATTEMPT_COLLECTION:&str = "login_attempts";
+
+/*- Failures allowed before locking, per account and per IP. IPs
+    get more, since many users can share one behind a NAT -*/
+const ACCOUNT_FAILURE_THRESHOLD:u32 = 5;
+const IP_FAILURE_THRESHOLD:u32 = 20;
+
+/*- The first lockout lasts this long, and doubles with every
+    failure after that, up to the maximum -*/
+const BASE_LOCKOUT:u64 = 30;
+const MAX_LOCKOUT:u64 = 60*60;
+
+/*- Failures older than this are forgotten -*/
+const FAILURE_WINDOW:u64 = 60*60*24;
+
+/*- Structs, enums, unions -*/
+/// # LoginAttempts
+/// Failed login attempts for a key, which is either
+/// `account:<email>` or `ip:<address>`. Keys for other things
+/// than logging in have a suffix, like `ip:<address>:magic-link`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct LoginAttempts {
+    pub key          : String,
+    pub failures     : u32,
+    pub last_failure : u64,
+    pub locked_until : u64,
+}
+
+/*- Functions -*/
+/*- The throttling key of an account -*/
+pub(crate) fn account_key(email:&str) -> String {
+    format!("account:{}", normalize_identifier(email))
+}
+
+/*- The throttling key of the client's IP -*/
+pub(crate) fn ip_key(stream:&TcpStream) -> String {
+    format!("ip:{}", utils::client_ip(stream))
+}
+
+/*- The throttling key of an account or IP for something other than
+    logging in, so that using it up doesn't lock the login itself -*/
+pub(crate) fn purpose_key(key:&str, purpose:&str) -> String {
+    format!("{}:{}", key, purpose)
+}
+
+/*- Check if any of the keys are locked. Returns
+    the seconds until all of them are unlocked -*/
+pub(crate) fn check(keys:&[&str]) -> Result<(), u64> {
+    let collection:Collection<LoginAttempts> = utils::establish_mclient::<LoginAttempts>(LOGIN_ATTEMPT_COLLECTION);
+    let now = utils::get_unix_epoch_time();
+
+    /*- Find the longest remaining lockout -*/
+    let retry_after = keys.iter()
+        .filter_map(|key| collection.find_one(doc!{ "key": *key }, None).ok().flatten())
+        .map(|attempts| attempts.locked_until.saturating_sub(now))
+        .max()
+        .unwrap_or(0);
+
+    match retry_after {
+        0 => Ok(()),
+        _ => Err(retry_after),
+    }
+}
+
+/*- Count a failed attempt, and lock the key if it has failed too often -*/
+pub(crate) fn record_failure(key:&str) -> () {
+    let collection:Collection<LoginAttempts> = utils::establish_mclient::<LoginAttempts>(LOGIN_ATTEMPT_COLLECTION);
+    let now = utils::get_unix_epoch_time();
+
+    /*- Start over if the last failure was long ago -*/
+    collection.delete_one(doc!{ "key": key, "last_failure": { "$lt": (now - FAILURE_WINDOW) as i64 } }, None).ok();
+
+    /*- Count the failure -*/
+    let attempts = collection.find_one_and_update(
+        doc!{ "key": key },
+        doc!{
+            "$inc": { "failures": 1 },
+            "$set": { "last_failure": now as i64 },
+            "$setOnInsert": { "locked_until": 0i64 },
+        },
+        FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build()
+    );
+    let attempts = match attempts {
+        Ok(Some(attempts)) => attempts,
+        _ => return,
+    };
+
+    /*- Lock exponentially longer for every failure over the threshold -*/
+    let threshold = match key.starts_with("ip:") {
+        true  => IP_FAILURE_THRESHOLD,
+        false => ACCOUNT_FAILURE_THRESHOLD,
+    };
+    if attempts.failures >= threshold {
+        let exponent = (attempts.failures - threshold).min(16);
+        let lockout  = (BASE_LOCKOUT << exponent).min(MAX_LOCKOUT);
+
+        collection.update_one(
+            doc!{ "key": key },
+            doc!{ "$set": { "locked_until": (now + lockout) as i64 } },
+            None
+        ).ok();
+    };
+}
+
+/*- Count a request to something which is limited however it turns
+    out, like mailing login links. Locks like failed logins do -*/
+pub(crate) fn record_request(key:&str) -> () {
+    record_failure(key)
+}
+
+/*- Get the failed attempts of a key -*/
+pub(crate) fn get(key:&str) -> Option<LoginAttempts> {
+    let collection:Collection<LoginAttempts> = utils::establish_mclient::<LoginAttempts>(LOGIN_ATTEMPT_COLLECTION);
+    collection.find_one(doc!{ "key": key }, None).ok().flatten()
+}
+
+/*- Forget the failures of a key after a successful login -*/
+pub(crate) fn record_success(key:&str) -> () {
+    let collection:Collection<LoginAttempts> = utils::establish_mclient::<LoginAttempts>(LOGIN_ATTEMPT_COLLECTION);
+    collection.delete_one(doc!{ "key": key }, None).ok();
+}
+
+/*- All keys which are currently locked -*/
+pub(crate) fn locked() -> Vec<LoginAttempts> {
+    let collection:Collection<LoginAttempts> = utils::establish_mclient::<LoginAttempts>(LOGIN_ATTEMPT_COLLECTION);
+    match collection.find(doc!{ "locked_until": { "$gt": utils::get_unix_epoch_time() as i64 } }, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect(),
+        Err(_)     => vec![],
+    }
+}
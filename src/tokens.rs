@@ -0,0 +1,263 @@
+/*- Refresh tokens & token revocation -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ utils, sessions, config::CONFIG, user::{ User, UserClaims, generate_suid } };
+use serde::{ Serialize, Deserialize };
+use mongodb::{
+    bson::doc,
+    sync::Collection,
+    options::UpdateOptions,
+};
+
+/*- Statics & Constants -*/
+const REFRESH_TOKEN_COLLECTION:&str = "refresh_tokens";
+const REVOKED_TOKEN_COLLECTION:&str = "revoked_tokens";
+const USER_REVOCATION_COLLECTION:&str = "user_revocations";
+
+/*- Structs, enums, unions -*/
+/// # RefreshToken
+/// A long-lived token which can be exchanged for a new access token.
+/// Only the hash of the token is stored. Every refresh token belongs
+/// to a `family`, which is all tokens descending from the same login,
+/// and which is also the id of that login's session.
+/// Refresh tokens are single-use, so if an already used one shows up
+/// again, it has been stolen and the whole family gets revoked.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct RefreshToken {
+    pub token_hash : String,
+    pub suid       : String,
+    pub family     : String,
+    pub created    : u64,
+    pub expires    : u64,
+    pub revoked    : bool,
+}
+
+/// # RevokedToken
+/// An access token which must no longer be accepted, even
+/// though it hasn't expired. Kept until its `exp` has passed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct RevokedToken {
+    pub jti : String,
+    pub exp : u64,
+}
+
+/// # UserRevocation
+/// Every access token issued to `suid` up to `revoked_before_micros`
+/// is rejected. Used for "log out everywhere". The cutoff is in
+/// microseconds, so that the client doing the revoking can be handed
+/// a new token straight away, while tokens issued just before still
+/// stop working. Tokens without `iat_micros`, and revocations made
+/// before it existed, are compared in seconds, revoking the whole
+/// second of `revoked_before`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct UserRevocation {
+    pub suid           : String,
+    pub revoked_before : u64,
+    #[serde(default)]
+    pub revoked_before_micros : u64,
+}
+
+/*- Functions -*/
+/*- Issue a new refresh token, starting a new family if none is given -*/
+pub(crate) fn issue_refresh_token(suid:&str, family:Option<&str>) -> String {
+    let token = utils::generate_token();
+    let now   = utils::get_unix_epoch_time();
+
+    /*- Store the hash -*/
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+    collection.insert_one(RefreshToken {
+        token_hash : utils::hash(&token),
+        suid       : suid.to_string(),
+        family     : family.map(|e| e.to_string()).unwrap_or_else(generate_suid),
+        created    : now,
+        expires    : now + CONFIG.refresh_token_lifetime,
+        revoked    : false,
+    }, None).ok();
+
+    /*- Only the client gets the plain token -*/
+    token
+}
+
+/*- Exchange a refresh token for a new one in the same family.
+    Returns the new token, the suid of its owner and the family -*/
+pub(crate) fn rotate_refresh_token(token:&str) -> Result<(String, String, String), ()> {
+    let token_hash = utils::hash(token);
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+
+    /*- Atomically mark the token as used, so that
+        two concurrent refreshes can't both succeed -*/
+    let refresh_token = collection.find_one_and_update(
+        doc!{ "token_hash": &token_hash, "revoked": false },
+        doc!{ "$set": { "revoked": true } },
+        None
+    ).map_err(|_| ())?;
+
+    match refresh_token {
+        Some(refresh_token) => {
+            /*- Expired -*/
+            if refresh_token.expires < utils::get_unix_epoch_time() { return Err(()); };
+
+            /*- Continue the family -*/
+            let new_token = issue_refresh_token(&refresh_token.suid, Some(&refresh_token.family));
+            Ok((new_token, refresh_token.suid, refresh_token.family))
+        },
+
+        /*- Either unknown, or already used. Reuse means
+            the token has leaked, so revoke the family -*/
+        None => {
+            if let Ok(Some(reused)) = collection.find_one(doc!{ "token_hash": &token_hash }, None) {
+                revoke_refresh_family(&reused.family);
+            };
+
+            Err(())
+        }
+    }
+}
+
+/*- The family (session) a refresh token belongs to, without using it
+    up. Used to check the session before the token is rotated -*/
+pub(crate) fn refresh_token_family(token:&str) -> Option<String> {
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+    collection.find_one(doc!{ "token_hash": utils::hash(token) }, None)
+        .ok()
+        .flatten()
+        .map(|refresh_token| refresh_token.family)
+}
+
+/*- Revoke every refresh token descending from the same login -*/
+pub(crate) fn revoke_refresh_family(family:&str) -> () {
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+    collection.update_many(
+        doc!{ "family": family },
+        doc!{ "$set": { "revoked": true } },
+        None
+    ).ok();
+}
+
+/*- Remove every refresh token of a user, used when deleting them -*/
+pub(crate) fn delete_refresh_tokens(suid:&str) -> () {
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
+
+/*- Revoke the family a (plain) refresh token belongs to -*/
+pub(crate) fn revoke_refresh_token(token:&str) -> () {
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+    if let Ok(Some(refresh_token)) = collection.find_one(doc!{ "token_hash": utils::hash(token) }, None) {
+        revoke_refresh_family(&refresh_token.family);
+    };
+}
+
+/*- Stop accepting an access token before it expires -*/
+pub(crate) fn revoke_access_token(claims:&UserClaims) -> () {
+    /*- Tokens issued before jti:s were introduced can't be revoked -*/
+    if claims.jti.is_empty() { return; };
+
+    let collection:Collection<RevokedToken> = utils::establish_mclient::<RevokedToken>(REVOKED_TOKEN_COLLECTION);
+
+    /*- Clean up entries for tokens which have expired by themselves -*/
+    collection.delete_many(doc!{ "exp": { "$lt": utils::get_unix_epoch_time() as i64 } }, None).ok();
+
+    collection.insert_one(RevokedToken {
+        jti : claims.jti.clone(),
+        exp : claims.exp as u64,
+    }, None).ok();
+}
+
+/*- Revoke every session, access and refresh token a user has -*/
+pub(crate) fn revoke_all_for_user(suid:&str) -> () {
+    sessions::revoke_all(suid);
+
+    /*- Refresh tokens -*/
+    let collection:Collection<RefreshToken> = utils::establish_mclient::<RefreshToken>(REFRESH_TOKEN_COLLECTION);
+    collection.update_many(
+        doc!{ "suid": suid },
+        doc!{ "$set": { "revoked": true } },
+        None
+    ).ok();
+
+    /*- Access tokens, which are rejected based on when they were issued -*/
+    let now = utils::get_unix_epoch_micros();
+    let collection:Collection<UserRevocation> = utils::establish_mclient::<UserRevocation>(USER_REVOCATION_COLLECTION);
+    collection.update_one(
+        doc!{ "suid": suid },
+        doc!{ "$set": {
+            "revoked_before"        : (now / 1_000_000) as i64,
+            "revoked_before_micros" : now as i64,
+        } },
+        UpdateOptions::builder().upsert(true).build()
+    ).ok();
+}
+
+/*- Check if an access token has been revoked, either by
+    itself or by all of its owner's tokens being revoked -*/
+pub(crate) fn is_revoked(claims:&UserClaims) -> bool {
+    /*- Revoked by jti. Tokens without one can't be revoked this way -*/
+    if !claims.jti.is_empty() {
+        let collection:Collection<RevokedToken> = utils::establish_mclient::<RevokedToken>(REVOKED_TOKEN_COLLECTION);
+        match collection.find_one(doc!{ "jti": &claims.jti }, None) {
+            Ok(Some(_)) => return true,
+            Ok(None)    => (),
+
+            /*- Fail closed if the database can't be reached -*/
+            Err(_) => return true,
+        };
+    };
+
+    /*- Revoked along with all of the user's tokens -*/
+    let collection:Collection<UserRevocation> = utils::establish_mclient::<UserRevocation>(USER_REVOCATION_COLLECTION);
+    match collection.find_one(doc!{ "suid": &claims.suid }, None) {
+        Ok(Some(revocation)) => is_revoked_by(claims, &revocation),
+        Ok(None) => false,
+        Err(_)   => true,
+    }
+}
+
+/*- If a revocation of all of a user's tokens covers a token -*/
+fn is_revoked_by(claims:&UserClaims, revocation:&UserRevocation) -> bool {
+    match (claims.iat_micros, revocation.revoked_before_micros) {
+        (0, _) | (_, 0) => (claims.iat as u64) <= revocation.revoked_before,
+        (issued, revoked_before) => issued <= revoked_before,
+    }
+}
+
+/*- Tests -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(iat:u64, iat_micros:u64) -> UserClaims {
+        serde_json::from_value(serde_json::json!({
+            "username": "alice", "uid": "uid", "suid": "suid", "exp": 0,
+            "iat": iat, "iat_micros": iat_micros,
+        })).unwrap()
+    }
+
+    fn revocation(revoked_before:u64, revoked_before_micros:u64) -> UserRevocation {
+        UserRevocation { suid: "suid".to_string(), revoked_before, revoked_before_micros }
+    }
+
+    #[test]
+    fn revocation_tells_apart_tokens_within_a_second() {
+        let revoked = revocation(1_700_000_000, 1_700_000_000_500_000);
+
+        assert!(is_revoked_by(&claims(1_700_000_000, 1_700_000_000_499_999), &revoked));
+        assert!(is_revoked_by(&claims(1_700_000_000, 1_700_000_000_500_000), &revoked));
+        assert!(!is_revoked_by(&claims(1_700_000_000, 1_700_000_000_500_001), &revoked));
+    }
+
+    #[test]
+    fn revocation_falls_back_to_seconds() {
+        /*- Tokens and revocations from before microseconds were stored -*/
+        assert!(is_revoked_by(&claims(1_700_000_000, 0), &revocation(1_700_000_000, 1_700_000_000_500_000)));
+        assert!(!is_revoked_by(&claims(1_700_000_001, 0), &revocation(1_700_000_000, 1_700_000_000_500_000)));
+        assert!(is_revoked_by(&claims(1_700_000_000, 1_700_000_000_999_999), &revocation(1_700_000_000, 0)));
+        assert!(!is_revoked_by(&claims(1_700_000_001, 1_700_000_001_000_000), &revocation(1_700_000_000, 0)));
+    }
+}
@@ -0,0 +1,318 @@
+/*- Two-factor authentication (RFC 6238 TOTP) -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ keys, utils, config::CONFIG, user::{ User, generate_suid } };
+use mongodb::{ bson::doc, sync::Collection };
+use aes_gcm::{ Aes256Gcm, Nonce, aead::{ Aead, Payload } };
+use sha2::{ Digest, Sha256 };
+use serde::{ Serialize, Deserialize };
+use rand::{ rngs::OsRng, RngCore };
+use hmac::{ Hmac, Mac };
+use sha1::Sha1;
+
+/*- Statics & Constants -*/
+const TOTP_STEP:u64 = 30;
+const TOTP_DIGITS:u32 = 6;
+const TOTP_SECRET_LEN:usize = 20;
+
+/*- How many steps a code may be off, to allow for clock drift -*/
+const TOTP_SKEW:u64 = 1;
+
+const RECOVERY_CODE_COUNT:usize = 10;
+const CHALLENGE_PURPOSE:&str = "2fa-challenge";
+const CHALLENGE_LIFETIME:u64 = 60*5;
+const CHALLENGE_COLLECTION:&str = "two_factor_challenges";
+
+/*- Encrypted secrets are stored as this prefix, followed by the
+    base64 of the nonce and ciphertext. Secrets stored before they
+    were encrypted have no prefix, and are encrypted on startup -*/
+const ENCRYPTED_SECRET_PREFIX:&str = "v1:";
+const NONCE_LEN:usize = 12;
+
+/*- Structs, enums, unions -*/
+/// # TwoFactorChallengeClaims
+/// Handed out by `/login` instead of an access token when the user has
+/// 2FA enabled. It proves the password was correct, and is exchanged
+/// together with a TOTP or recovery code at `/login/2fa`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct TwoFactorChallengeClaims {
+    pub suid    : String,
+    pub purpose : String,
+    pub jti     : String,
+    pub exp     : usize,
+}
+
+/// # TwoFactorChallenge
+/// A challenge which has been handed out. Like login links, the
+/// signature proves it was issued by us, this makes sure it's
+/// only ever exchanged once.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct TwoFactorChallenge {
+    pub jti     : String,
+    pub suid    : String,
+    pub expires : u64,
+    pub used    : bool,
+}
+
+/*- Functions -*/
+/*- Generate a new base32 encoded secret -*/
+pub(crate) fn generate_secret() -> String {
+    let mut bytes = [0u8; TOTP_SECRET_LEN];
+    OsRng.fill_bytes(&mut bytes);
+
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes)
+}
+
+/*- If secrets can be encrypted, which 2FA can't be set up without -*/
+pub(crate) fn is_available() -> bool {
+    CONFIG.totp_encryption_key.is_some()
+}
+
+/*- The AES-256 key derived from TOTP_ENCRYPTION_KEY -*/
+fn cipher() -> Option<Aes256Gcm> {
+    let key = Sha256::digest(CONFIG.totp_encryption_key.as_ref()?.as_bytes());
+    <Aes256Gcm as aes_gcm::KeyInit>::new_from_slice(&key).ok()
+}
+
+/*- Encrypt a secret to store it on a user. The suid is authenticated
+    along with it, so that it can't be copied to another user -*/
+pub(crate) fn encrypt_secret(secret:&str, suid:&str) -> Option<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(cipher()?.encrypt(Nonce::from_slice(&nonce), Payload { msg: secret.as_bytes(), aad: suid.as_bytes() }).ok()?);
+
+    Some(format!("{}{}", ENCRYPTED_SECRET_PREFIX, base64::encode(sealed)))
+}
+
+/*- Decrypt a secret stored on a user. Ones stored in
+    plain text, from before they were encrypted, are kept -*/
+pub(crate) fn decrypt_secret(stored:&str, suid:&str) -> Option<String> {
+    let sealed = match stored.strip_prefix(ENCRYPTED_SECRET_PREFIX) {
+        Some(sealed) => base64::decode(sealed).ok()?,
+        None => return Some(stored.to_string()),
+    };
+    if sealed.len() <= NONCE_LEN { return None; };
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let secret = cipher()?.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: suid.as_bytes() }).ok()?;
+    String::from_utf8(secret).ok()
+}
+
+/*- Encrypt the secrets stored before they were encrypted -*/
+pub(crate) fn encrypt_plaintext_secrets() -> () {
+    if !is_available() { return; };
+
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    let filter = doc!{ "totp_secret": { "$type": "string", "$not": { "$regex": format!("^{}", ENCRYPTED_SECRET_PREFIX) } } };
+    let users = match collection.find(filter, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect::<Vec<User>>(),
+        Err(_) => return,
+    };
+
+    for user in users {
+        let encrypted = user.totp_secret.as_ref().and_then(|secret| encrypt_secret(secret, &user.suid));
+        if let (Some(secret), Some(encrypted)) = (&user.totp_secret, encrypted) {
+            collection.update_one(
+                doc!{ "suid": &user.suid, "totp_secret": secret },
+                doc!{ "$set": { "totp_secret": encrypted } },
+                None
+            ).ok();
+        };
+    };
+}
+
+/*- The URI authenticator apps read from QR codes -*/
+pub(crate) fn otpauth_uri(secret:&str, account:&str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        utils::url_encode(&CONFIG.totp_issuer), utils::url_encode(account), secret,
+        utils::url_encode(&CONFIG.totp_issuer), TOTP_DIGITS, TOTP_STEP
+    )
+}
+
+/*- Check a code against a secret. Returns the time step the code was
+    for, which must be greater than `last_step` so codes can't be replayed -*/
+pub(crate) fn verify_code(secret:&str, code:&str, last_step:u64) -> Option<u64> {
+    let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)?;
+    let code = code.trim();
+    let current_step = utils::get_unix_epoch_time() / TOTP_STEP;
+
+    /*- Check the surrounding steps too -*/
+    (current_step.saturating_sub(TOTP_SKEW)..=current_step + TOTP_SKEW)
+        .filter(|step| *step > last_step)
+        .find(|step| generate_code(&key, *step) == code)
+}
+
+/*- HOTP (RFC 4226) for a time step -*/
+fn generate_code(key:&[u8], step:u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    /*- Dynamic truncation -*/
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        |  (hash[offset + 3] as u32);
+
+    format!("{:0width$}", binary % 10u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize)
+}
+
+/*- Generate one-time recovery codes. These are shown once,
+    and stored hashed (using utils::hash) on the user -*/
+pub(crate) fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let token = utils::generate_token();
+            format!("{}-{}", &token[0..5], &token[5..10])
+        })
+        .collect::<Vec<String>>()
+}
+
+/*- Check a TOTP or recovery code for a user with 2FA enabled,
+    and use it up so that the same code can't be used again -*/
+pub(crate) fn consume_second_factor(user:&User, code:&str) -> bool {
+    let secret = match (&user.totp_secret, user.totp_enabled) {
+        (Some(secret), true) => decrypt_secret(secret, &user.suid),
+        _ => return false,
+    };
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+
+    /*- TOTP code. Only accept it if no later step has been used -*/
+    if let Some(step) = secret.and_then(|secret| verify_code(&secret, code, user.totp_last_step)) {
+        return match collection.update_one(
+            doc!{ "suid": &user.suid, "totp_last_step": { "$lt": step as i64 } },
+            doc!{ "$set": { "totp_last_step": step as i64 } },
+            None
+        ) {
+            Ok(result) => result.modified_count > 0,
+            Err(_) => false,
+        };
+    };
+
+    /*- Recovery code, which is removed once used -*/
+    let code_hash = utils::hash(code.trim());
+    match collection.update_one(
+        doc!{ "suid": &user.suid, "recovery_codes": &code_hash },
+        doc!{ "$pull": { "recovery_codes": &code_hash } },
+        None
+    ) {
+        Ok(result) => result.modified_count > 0,
+        Err(_) => false,
+    }
+}
+
+/*- Create the challenge token for the second login step -*/
+pub(crate) fn create_challenge(suid:&str) -> Result<String, ()> {
+    let expires = utils::get_unix_epoch_time() + CHALLENGE_LIFETIME;
+    let claims = TwoFactorChallengeClaims {
+        suid    : suid.to_string(),
+        purpose : CHALLENGE_PURPOSE.to_string(),
+        jti     : generate_suid(),
+        exp     : expires as usize,
+    };
+
+    /*- Clean up challenges which have expired by themselves -*/
+    let collection:Collection<TwoFactorChallenge> = utils::establish_mclient::<TwoFactorChallenge>(CHALLENGE_COLLECTION);
+    collection.delete_many(doc!{ "expires": { "$lt": utils::get_unix_epoch_time() as i64 } }, None).ok();
+
+    collection.insert_one(TwoFactorChallenge {
+        jti     : claims.jti.clone(),
+        suid    : suid.to_string(),
+        expires,
+        used    : false,
+    }, None).map_err(|_| ())?;
+
+    Ok(keys::sign(&claims))
+}
+
+/*- Check a challenge token which hasn't been used, and return its claims -*/
+pub(crate) fn decode_challenge(token:&str) -> Result<TwoFactorChallengeClaims, ()> {
+    let claims = match keys::verify::<TwoFactorChallengeClaims>(token) {
+        Ok(claims) if claims.purpose == CHALLENGE_PURPOSE => claims,
+        _ => return Err(()),
+    };
+
+    let collection:Collection<TwoFactorChallenge> = utils::establish_mclient::<TwoFactorChallenge>(CHALLENGE_COLLECTION);
+    match collection.find_one(doc!{ "jti": &claims.jti, "suid": &claims.suid, "used": false }, None) {
+        Ok(Some(_)) => Ok(claims),
+        _ => Err(()),
+    }
+}
+
+/*- Use up a challenge once the second factor has been checked. Fails
+    if it has been used in the meantime, atomically so only once -*/
+pub(crate) fn consume_challenge(claims:&TwoFactorChallengeClaims) -> bool {
+    let collection:Collection<TwoFactorChallenge> = utils::establish_mclient::<TwoFactorChallenge>(CHALLENGE_COLLECTION);
+    matches!(collection.find_one_and_update(
+        doc!{ "jti": &claims.jti, "suid": &claims.suid, "used": false },
+        doc!{ "$set": { "used": true } },
+        None
+    ), Ok(Some(_)))
+}
+
+/*- Remove every challenge of a user, used when deleting them -*/
+pub(crate) fn delete_challenges(suid:&str) -> () {
+    let collection:Collection<TwoFactorChallenge> = utils::establish_mclient::<TwoFactorChallenge>(CHALLENGE_COLLECTION);
+    collection.delete_many(doc!{ "suid": suid }, None).ok();
+}
+
+/*- Tests -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*- The SHA1 seed from RFC 6238 appendix B -*/
+    const RFC_6238_KEY:&[u8] = b"12345678901234567890";
+
+    #[test]
+    fn generate_code_matches_rfc_6238() {
+        /*- The RFC lists 8 digit codes, we use their last 6 -*/
+        for (time, code) in [
+            (59u64,        "287082"),
+            (1111111109,   "081804"),
+            (1111111111,   "050471"),
+            (1234567890,   "005924"),
+            (2000000000,   "279037"),
+            (20000000000,  "353130"),
+        ] {
+            assert_eq!(generate_code(RFC_6238_KEY, time / TOTP_STEP), code);
+        };
+    }
+
+    #[test]
+    fn verify_code_accepts_current_and_rejects_replayed() {
+        let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, RFC_6238_KEY);
+        let step = utils::get_unix_epoch_time() / TOTP_STEP;
+        let code = generate_code(RFC_6238_KEY, step);
+
+        assert_eq!(verify_code(&secret, &code, 0), Some(step));
+        assert_eq!(verify_code(&secret, &code, step), None);
+        assert_eq!(verify_code(&secret, "not a code", 0), None);
+    }
+
+    #[test]
+    fn encrypted_secret_is_bound_to_user() {
+        let secret = generate_secret();
+        let stored = encrypt_secret(&secret, "suid-a").unwrap();
+
+        assert!(stored.starts_with(ENCRYPTED_SECRET_PREFIX));
+        assert!(!stored.contains(&secret));
+        assert_eq!(decrypt_secret(&stored, "suid-a"), Some(secret));
+        assert_eq!(decrypt_secret(&stored, "suid-b"), None);
+    }
+
+    #[test]
+    fn plaintext_secret_is_kept() {
+        assert_eq!(decrypt_secret("JBSWY3DPEHPK3PXP", "suid-a"), Some("JBSWY3DPEHPK3PXP".to_string()));
+    }
+}
@@ -5,14 +5,20 @@
 use regex;
 use uuid::Uuid;
 use fastserve::HeaderReturn;
-use std::{ time, thread, fmt, collections::HashMap };
+use std::{ time, thread, fmt, net::TcpStream, collections::HashMap };
 use serde::{ Serialize, Deserialize, de::DeserializeOwned };
 use jsonwebtoken::{ encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey, TokenData };
 
 use crate::safe_user::SafeUser;
-
-/*- Constants -*/
-const SECRET_KEY:&str = "Secret123";
+use crate::{ keys, tokens, sessions, api_keys, utils, policy, profile, config::CONFIG, dict::DICTIONARY };
+use crate::api_keys::Scope;
+use crate::roles::{ self, Role, Permission };
+use crate::audit::{ self, EventKind, Outcome };
+use crate::moderation::{ self, AccountState };
+use unicode_normalization::UnicodeNormalization;
+use caseless::default_case_fold_str;
+use mongodb::{ bson::doc, sync::Collection, IndexModel, options::IndexOptions };
+use chrono::NaiveDate;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct User {
@@ -22,7 +28,66 @@ pub(crate) struct User {
     pub email       : String, 
     pub uid         : String,
     pub suid        : String,
-    pub age         : u8,
+
+    /*- Optional, unless there is a minimum age. The age
+        is only shown on the profile if the user wants -*/
+    #[serde(default)]
+    pub date_of_birth : Option<NaiveDate>,
+    #[serde(default)]
+    pub show_age      : bool,
+
+    /*- New accounts stay pending until their email is verified.
+        Accounts created before verification existed count as verified -*/
+    #[serde(default = "verified_by_default")]
+    pub email_verified : bool,
+
+    /*- Two-factor authentication. The secret is set when enrolling,
+        but only used for logging in once the user has confirmed it -*/
+    #[serde(default)]
+    pub totp_secret    : Option<String>,
+    #[serde(default)]
+    pub totp_enabled   : bool,
+    #[serde(default)]
+    pub totp_last_step : u64,
+
+    /*- Hashes of the unused recovery codes -*/
+    #[serde(default)]
+    pub recovery_codes : Vec<String>,
+
+    /*- When the account will be deleted, if the user has asked for it -*/
+    #[serde(default)]
+    pub deletion_scheduled : Option<u64>,
+
+    /*- Roles granted on top of the User role -*/
+    #[serde(default)]
+    pub roles : Vec<Role>,
+
+    /*- Suspended, banned or deactivated, and why -*/
+    #[serde(default)]
+    pub account_state : AccountState,
+    #[serde(default)]
+    pub state_reason  : Option<String>,
+
+    /*- Who created the invite code the account was registered with -*/
+    #[serde(default)]
+    pub invited_by    : Option<String>,
+
+    /*- Public profile fields, see profile.rs -*/
+    #[serde(default)]
+    pub bio       : Option<String>,
+    #[serde(default)]
+    pub location  : Option<String>,
+    #[serde(default)]
+    pub website   : Option<String>,
+    #[serde(default)]
+    pub pronouns  : Option<String>,
+    #[serde(default)]
+    pub banner    : bool,
+
+    /*- When the account was created. Unknown
+        for accounts created before it was stored -*/
+    #[serde(default)]
+    pub created   : Option<u64>,
 }
 
 /*- The default users claims -*/
@@ -32,9 +97,57 @@ pub(crate) struct UserClaims {
     pub uid     : String,
     pub suid    : String,
     pub exp     : usize,
+
+    /*- Unique token id, used for revocation. Tokens
+        issued before it was introduced don't have one -*/
+    #[serde(default)]
+    pub jti     : String,
+
+    /*- When the token was issued -*/
+    #[serde(default)]
+    pub iat     : usize,
+
+    /*- The same in microseconds, so that revoking all of a user's
+        tokens can tell apart tokens issued within the same second -*/
+    #[serde(default)]
+    pub iat_micros : u64,
+
+    /*- The user's roles when the token was issued -*/
+    #[serde(default)]
+    pub roles   : Vec<Role>,
+
+    /*- The session (login) the token belongs to. Tokens
+        issued before sessions existed don't have one -*/
+    #[serde(default)]
+    pub sid     : String,
+
+    /*- What the token may be used for. Only set for API keys and
+        third-party apps, tokens from logging in may be used for anything -*/
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes  : Option<Vec<Scope>>,
 }
 
 /*- Fcuntion implementations -*/
+impl UserClaims {
+    /*- If the token's roles grant a permission -*/
+    pub fn has_permission(&self, permission:Permission) -> bool {
+        roles::has_permission(&self.roles, permission)
+    }
+
+    /*- If the token may be used for something -*/
+    pub fn has_scope(&self, scope:Scope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None         => true,
+        }
+    }
+
+    /*- If the claims come from an API key or a third-party app, rather than logging in -*/
+    pub fn is_delegated(&self) -> bool {
+        self.scopes.is_some()
+    }
+}
+
 impl Default for User {
     fn default() -> Self {
         User {
@@ -44,7 +157,24 @@ impl Default for User {
             email       : String::new(),
             uid         : String::new(),
             suid        : String::new(),
-            age         : 0,
+            date_of_birth  : None,
+            show_age       : false,
+            email_verified : false,
+            totp_secret    : None,
+            totp_enabled   : false,
+            totp_last_step : 0,
+            recovery_codes : vec![],
+            deletion_scheduled : None,
+            roles : vec![],
+            account_state : AccountState::Active,
+            state_reason  : None,
+            invited_by    : None,
+            bio       : None,
+            location  : None,
+            website   : None,
+            pronouns  : None,
+            banner    : false,
+            created   : None,
         }
     }
 }
@@ -54,65 +184,197 @@ impl fmt::Debug for User {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "User {{ username: {}, displayname : {}, password: {}, email: {}, uid: {}, age: {} }}",
-            self.username, self.displayname , self.password, self.email, self.uid, self.age
+            "User {{ username: {}, displayname : {}, password: {}, email: {}, uid: {}, date_of_birth: {:?}, email_verified: {} }}",
+            self.username, self.displayname , self.password, self.email, self.uid, self.date_of_birth, self.email_verified
         )
     }
 }
 
 impl User {
 
-    /*- Create a JWT token -*/
-    pub fn create__JWT__token(user:User) -> String {
+    /*- Create a JWT token belonging to a session -*/
+    pub fn create__JWT__token(user:User, sid:&str) -> String {
+        User::create__scoped__JWT__token(user, sid, None)
+    }
+
+    /*- Create a JWT token which may only be used for some scopes -*/
+    pub fn create__scoped__JWT__token(user:User, sid:&str, scopes:Option<Vec<Scope>>) -> String {
+        /*- Like API keys, third-party apps can't moderate -*/
+        let roles = match scopes {
+            Some(_) => vec![Role::User],
+            None    => user.effective_roles(),
+        };
+
         /*- Get the claims -*/
+        let issued = utils::get_unix_epoch_micros();
         let user_claims = UserClaims {
             username: user.username.clone(),
             uid     : user.uid.clone(),
             suid    : user.suid.clone(),
             exp     : get_expiration_time(),
+            jti     : generate_suid(),
+            iat     : (issued / 1_000_000) as usize,
+            iat_micros : issued,
+            roles   : roles,
+            sid     : sid.to_string(),
+            scopes,
         };
 
-        /*- Encode the claims -*/
-        let token = encode(
-            &Header::default(),
-            &user_claims,
-            &EncodingKey::from_secret(SECRET_KEY.as_ref())
-        ).expect("Failed to encode token");
-
-        /*- Return the token -*/
-        token
+        /*- Sign the claims with the current signing key -*/
+        keys::sign(&user_claims)
     }
 
     /*- Decode a JWT token -*/
     pub fn decode__JWT__token(token:&str) -> Result<UserClaims, ()> {
-        /*- Decode the token -*/
-        let token = decode::<UserClaims>(
-            &token,
-            &DecodingKey::from_secret(
-                SECRET_KEY.as_ref()
-            ),
-            &Validation::default()
-        );
-
-        /*- Check token decode status and return the token claims / data -*/
-        return match token {
-            Ok(token) => Ok(token.claims),
-            Err(_) => Err(())
+        /*- Verify against the key named in the token's kid header -*/
+        keys::verify::<UserClaims>(token)
+    }
+
+    /*- Every role the user has. The users listed in ADMIN_SUIDS
+        are always admins, so that the first admin can grant roles -*/
+    pub fn effective_roles(&self) -> Vec<Role> {
+        let mut roles = vec![Role::User];
+        roles.extend(self.roles.iter().filter(|role| **role != Role::User));
+
+        if CONFIG.admin_suids.iter().any(|admin| admin == &self.suid) && !roles.contains(&Role::Admin) {
+            roles.push(Role::Admin);
         };
+
+        roles
+    }
+
+    /*- The user's age in whole years, if their date of birth is known -*/
+    pub fn age(&self) -> Option<u32> {
+        self.date_of_birth.map(|date_of_birth| policy::age(date_of_birth, chrono::Utc::now().naive_utc().date()))
     }
 
     /*- Convert to SafeUser -*/
     pub fn to_safe(user:User) -> SafeUser {
         return SafeUser {
+            age         : user.age().filter(|_| user.show_age),
+            banner      : Some(profile::banner_url(&user.suid)).filter(|_| user.banner),
             username    : user.username,
             displayname : user.displayname,
             suid        : user.suid,
-            age         : user.age,
+            bio         : user.bio,
+            location    : user.location,
+            website     : user.website,
+            pronouns    : user.pronouns,
+            joined      : user.created,
         }
     }
 }
 
 /*- Utility functions -*/
+fn verified_by_default() -> bool { true }
+
+/*- What to respond with when writing a user broke one of the
+    unique indexes of migrate_identities. None for other errors -*/
+pub(crate) fn in_use_message(error:&mongodb::error::Error) -> Option<&'static str> {
+    utils::duplicate_key_index(error).map(|index| match index.as_str() {
+        "email_1" => DICTIONARY.error.in_use.email,
+        _         => DICTIONARY.error.in_use.username,
+    })
+}
+
+/*- Get a user by their suid -*/
+pub(crate) fn get_user(suid:&str) -> Option<User> {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    collection.find_one(doc!{ "suid": suid }, None).ok().flatten()
+}
+
+/*- If a user has verified their email -*/
+pub(crate) fn is_email_verified(suid:&str) -> bool {
+    get_user(suid).map(|user| user.email_verified).unwrap_or(false)
+}
+
+/*- Get a user by their username or email. Usernames
+    can't contain "@", so that tells the two apart -*/
+pub(crate) fn find_by_identifier(identifier:&str) -> Option<User> {
+    let identifier = normalize_identifier(identifier);
+    let field = if identifier.contains('@') { "email" } else { "username" };
+
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+    collection.find_one(doc!{ field: identifier }, None).ok().flatten()
+}
+
+/*- Usernames and emails are stored normalized: trimmed, Unicode NFKC
+    and case-folded, so that "Bob", "bob" and "ｂｏｂ" are the same user -*/
+pub(crate) fn normalize_identifier(value:&str) -> String {
+    let composed = value.trim().nfkc().collect::<String>();
+    default_case_fold_str(&composed).nfkc().collect::<String>()
+}
+
+/*- Normalize the usernames and emails of accounts created before they
+    were, and make them unique. Accounts which would clash are left as
+    they are and logged, as they need to be resolved by hand -*/
+pub(crate) fn migrate_identities() -> () {
+    let collection:Collection<User> = utils::establish_mclient::<User>("test");
+
+    /*- The indexes come first, so that no rewrite can make two accounts the same -*/
+    for field in ["username", "email"] {
+        if let Err(e) = collection.create_index(
+            IndexModel::builder()
+                .keys(doc!{ field: 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None
+        ) {
+            println!("Failed to create the unique {} index: {}", field, e);
+        };
+    };
+
+    let users = match collection.find(None, None) {
+        Ok(cursor) => cursor.filter_map(|e| e.ok()).collect::<Vec<User>>(),
+        Err(_) => return println!("Failed to read users, identities were not migrated"),
+    };
+
+    let (updates, clashes) = plan_identity_migration(&users);
+    for user in clashes {
+        println!("Not normalizing the identity of {}, it would clash with another account", user.suid);
+    };
+
+    for (user, username, email) in updates {
+        if let Err(e) = collection.update_one(
+            doc!{ "suid": &user.suid },
+            doc!{ "$set": { "username": &username, "email": &email } },
+            None
+        ) {
+            println!("Failed to normalize the identity of {}: {}", user.suid, e);
+        };
+    };
+}
+
+/*- Which accounts get which normalized username and email. Accounts
+    whose username or email would then be the same as another account's
+    are returned separately, and keep their identity as it is -*/
+fn plan_identity_migration(users:&[User]) -> (Vec<(&User, String, String)>, Vec<&User>) {
+    let normalized = users.iter()
+        .map(|user| (user, normalize_identifier(&user.username), normalize_identifier(&user.email)))
+        .collect::<Vec<(&User, String, String)>>();
+
+    /*- How many accounts end up with each identity -*/
+    let mut usernames:HashMap<&str, usize> = HashMap::new();
+    let mut emails:HashMap<&str, usize> = HashMap::new();
+    for (_, username, email) in &normalized {
+        *usernames.entry(username).or_insert(0) += 1;
+        *emails.entry(email).or_insert(0) += 1;
+    };
+
+    let mut updates = Vec::new();
+    let mut clashes = Vec::new();
+    for (user, username, email) in &normalized {
+        if *username == user.username && *email == user.email { continue; };
+
+        match usernames[username.as_str()] > 1 || emails[email.as_str()] > 1 {
+            true  => clashes.push(*user),
+            false => updates.push((*user, username.clone(), email.clone())),
+        };
+    };
+
+    (updates, clashes)
+}
+
 pub fn generate_uuid() -> String {
     Uuid::new_v4().as_hyphenated().to_string()
 }
@@ -138,8 +400,9 @@ pub fn get_expiration_time() -> usize {
     /*- Get the current time -*/
     let now = time::SystemTime::now();
 
-    /*- Get the expiration time -*/
-    let expiration_time = now + time::Duration::from_secs(60*60*24*30);
+    /*- Get the expiration time. Access tokens are short-lived,
+        clients use their refresh token to get new ones -*/
+    let expiration_time = now + time::Duration::from_secs(CONFIG.access_token_lifetime);
 
     /*- Convert the expiration time to unix time -*/
     expiration_time.duration_since(time::UNIX_EPOCH).unwrap().as_secs() as usize
@@ -148,9 +411,10 @@ pub fn get_expiration_time() -> usize {
 
 /*- Fully check if user is authorized, and
     return a bool dependent on if they are -*/
-pub(crate) fn authenticate(headers:HeaderReturn) -> AuthorizationStatus {
+pub(crate) fn authenticate(stream:&TcpStream, headers:HeaderReturn) -> AuthorizationStatus {
     /*- Initialize the user -*/
     let token:String;
+    let user_agent:String;
 
     /*- Get the headers -*/
     if let HeaderReturn::Values(headers) = headers {
@@ -164,23 +428,143 @@ pub(crate) fn authenticate(headers:HeaderReturn) -> AuthorizationStatus {
                 }
             }
         }.to_string().replace("Bearer ", "");
+        user_agent = sessions::user_agent(&headers);
     }
     /*- If parsing headers was unsuccessful -*/
     else { return AuthorizationStatus::Err; };
 
-    /*- Decode the token -*/
-    let user_claims = User::decode__JWT__token(&token);
+    /*- API keys are sent the same way as JWTs -*/
+    let result = match token.starts_with(api_keys::API_KEY_PREFIX) {
+        true  => authenticate_api_key(&token),
+        false => authenticate_token(&token),
+    };
 
-    /*- Return -*/
-    match user_claims {
-        Ok(u)   => return AuthorizationStatus::Authorized(u),
-        Err(_)  => return AuthorizationStatus::Unauthorized
+    /*- Return, recording why rejected credentials were -*/
+    match result {
+        Ok(claims) => AuthorizationStatus::Authorized(claims),
+        Err((reason, suid)) => {
+            audit::record(stream, &user_agent, EventKind::AuthenticationFailed, Outcome::Failure, suid.as_deref(), Some(reason));
+            AuthorizationStatus::Unauthorized
+        }
     }
 }
 
+/*- Authenticate for managing the account itself, which API keys and
+    third-party apps may not do. Errors are the status to respond with -*/
+pub(crate) fn authenticate_first_party(stream:&TcpStream, headers:HeaderReturn) -> Result<UserClaims, u16> {
+    match authenticate(stream, headers) {
+        AuthorizationStatus::Authorized(claims) if claims.is_delegated() => Err(403),
+        AuthorizationStatus::Authorized(claims) => Ok(claims),
+        _ => Err(401),
+    }
+}
+
+/*- Check a JWT. Errors are the reason, and the suid if it's known -*/
+fn authenticate_token(token:&str) -> Result<UserClaims, (&'static str, Option<String>)> {
+    match User::decode__JWT__token(token) {
+        /*- Logged out tokens must stop working immediately -*/
+        Ok(u) if tokens::is_revoked(&u) => Err(("revoked token", Some(u.suid))),
+
+        /*- And so must tokens of revoked sessions -*/
+        Ok(u) if !u.sid.is_empty() && !sessions::check_and_touch(&u.sid) => Err(("revoked session", Some(u.suid))),
+
+        /*- Suspended and banned users can't use tokens issued before -*/
+        Ok(u) if !moderation::is_active(&u.suid) => Err(("inactive account", Some(u.suid))),
+        Ok(u)   => Ok(u),
+        Err(_)  => Err(("invalid or expired token", None)),
+    }
+}
+
+/*- Check an API key, and turn it into claims. API keys get no
+    roles besides User, so they can't be used for moderation -*/
+fn authenticate_api_key(key:&str) -> Result<UserClaims, (&'static str, Option<String>)> {
+    let api_key = match api_keys::find_api_key(key) {
+        Some(api_key) => api_key,
+        None => return Err(("invalid or expired API key", None)),
+    };
+    let user = match get_user(&api_key.suid) {
+        Some(user) => user,
+        None => return Err(("API key of a deleted user", Some(api_key.suid))),
+    };
+    if !user.account_state.is_active() {
+        return Err(("API key of an inactive account", Some(user.suid)));
+    };
+
+    Ok(UserClaims {
+        username : user.username,
+        uid      : user.uid,
+        suid     : user.suid,
+        exp      : api_key.expires.unwrap_or(0) as usize,
+        jti      : String::new(),
+        iat      : api_key.created as usize,
+        iat_micros : api_key.created * 1_000_000,
+        roles    : vec![Role::User],
+        sid      : String::new(),
+        scopes   : Some(api_key.scopes),
+    })
+}
+
 #[derive(Debug)]
 pub(crate) enum AuthorizationStatus{
     Authorized(UserClaims),
     Unauthorized,
     Err,
 }
+
+/*- Tests -*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(suid:&str, username:&str, email:&str) -> User {
+        serde_json::from_value(serde_json::json!({
+            "username": username, "displayname": username, "password": "",
+            "email": email, "uid": suid, "suid": suid,
+        })).unwrap()
+    }
+
+    fn suids<'a>(users:impl IntoIterator<Item = &'a User>) -> Vec<&'a str> {
+        users.into_iter().map(|user| user.suid.as_str()).collect()
+    }
+
+    #[test]
+    fn migration_normalizes_identities() {
+        let users = vec![
+            user("a", "Alice", "Alice@Example.com"),
+            user("b", "bob", "bob@example.com"),
+        ];
+        let (updates, clashes) = plan_identity_migration(&users);
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0.suid, "a");
+        assert_eq!((updates[0].1.as_str(), updates[0].2.as_str()), ("alice", "alice@example.com"));
+        assert!(clashes.is_empty());
+    }
+
+    #[test]
+    fn migration_skips_case_variant_usernames() {
+        let users = vec![
+            user("a", "Bob", "a@example.com"),
+            user("b", "bob", "b@example.com"),
+            user("c", "BOB", "c@example.com"),
+        ];
+        let (updates, clashes) = plan_identity_migration(&users);
+
+        /*- "bob" is already normalized, so it's left as it is too -*/
+        assert!(updates.is_empty());
+        assert_eq!(suids(clashes), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn migration_skips_case_variant_emails() {
+        let users = vec![
+            user("a", "alice", "A@x.com"),
+            user("b", "bob", "a@X.COM"),
+            user("c", "Carol", "carol@x.com"),
+        ];
+        let (updates, clashes) = plan_identity_migration(&users);
+
+        assert_eq!(suids(updates.iter().map(|(user, _, _)| *user)), vec!["c"]);
+        assert_eq!(suids(clashes), vec!["a", "b"]);
+    }
+}
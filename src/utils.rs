@@ -14,7 +14,9 @@ use crate::api::{ MONGO_CLIENT_URI_STRING, REQUIRED_HEADERS };
 use fastserve::HeaderReturn;
 use crate::user::User;
 use sha3::{ Digest, Sha3_256 };
+use rand::{ rngs::OsRng, RngCore };
 use mongodb::{
+    error::{ Error, ErrorKind, WriteFailure },
     bson::{
         doc,
         Document
@@ -25,6 +27,7 @@ use mongodb::{
         Database
     },
 };
+use std::net::TcpStream;
 use std::time::{
     SystemTime,
     UNIX_EPOCH
@@ -81,4 +84,58 @@ pub(super) fn get_unix_epoch_time() -> u64 {
 
     /*- Convert to unix epoch time -*/
     return current_time.duration_since(UNIX_EPOCH).unwrap().as_secs();
-}
\ No newline at end of file
+}
+
+/*- The current unix epoch time in microseconds -*/
+pub(super) fn get_unix_epoch_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
+}
+
+/*- Generate a random, URL-safe token. These are
+    meant to be stored hashed, using hash() -*/
+pub(crate) fn generate_token() -> String {
+    /*- 256 bits of randomness -*/
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    /*- Hex encode -*/
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
+}
+/*- The IP address of the client -*/
+pub(crate) fn client_ip(stream:&TcpStream) -> String {
+    match stream.peer_addr() {
+        Ok(address) => address.ip().to_string(),
+        Err(_)      => "unknown".to_string(),
+    }
+}
+
+/*- Percent-encode everything but unreserved characters -*/
+pub(crate) fn url_encode(value:&str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect::<String>()
+}
+
+/*- If a write failed because it broke a unique index -*/
+pub(crate) fn is_duplicate_key(error:&Error) -> bool {
+    duplicate_key_index(error).is_some()
+}
+
+/*- The name of the unique index a write broke, like "email_1". The
+    server only names it in the message: "... index: email_1 dup key: ..." -*/
+pub(crate) fn duplicate_key_index(error:&Error) -> Option<String> {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000 => Some(
+            write_error.message
+                .split("index: ")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap_or_default()
+                .to_string()
+        ),
+        _ => None,
+    }
+}
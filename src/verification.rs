@@ -0,0 +1,61 @@
+/*- Email verification -*/
+/*- Global allowances -*/
+#![allow(
+    dead_code,
+    unused_variables,
+    unused_imports
+)]
+
+/*- Imports -*/
+use crate::{ keys, utils, config::CONFIG, user::User, mail::{ MAILER, Mail } };
+use serde::{ Serialize, Deserialize };
+
+/*- Statics & Constants -*/
+const VERIFICATION_PURPOSE:&str = "verify-email";
+const VERIFICATION_TOKEN_LIFETIME:u64 = 60*60*24*2;
+
+/*- Structs, enums, unions -*/
+/// # EmailVerificationClaims
+/// The claims of the signed token sent to new users. The email is
+/// included so that the token stops working if the email changes.
+/// The purpose makes sure no other kind of token can be used here.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct EmailVerificationClaims {
+    pub suid    : String,
+    pub email   : String,
+    pub purpose : String,
+    pub exp     : usize,
+}
+
+/*- Functions -*/
+/*- Create a signed verification token for a user's current email -*/
+pub(crate) fn create_verification_token(user:&User) -> String {
+    keys::sign(&EmailVerificationClaims {
+        suid    : user.suid.clone(),
+        email   : user.email.clone(),
+        purpose : VERIFICATION_PURPOSE.to_string(),
+        exp     : (utils::get_unix_epoch_time() + VERIFICATION_TOKEN_LIFETIME) as usize,
+    })
+}
+
+/*- Check a verification token and return its claims -*/
+pub(crate) fn decode_verification_token(token:&str) -> Result<EmailVerificationClaims, ()> {
+    match keys::verify::<EmailVerificationClaims>(token) {
+        Ok(claims) if claims.purpose == VERIFICATION_PURPOSE => Ok(claims),
+        _ => Err(()),
+    }
+}
+
+/*- Mail a verification link to the user -*/
+pub(crate) fn send_verification_mail(user:&User) -> Result<(), String> {
+    let link = format!("{}/verify-email/{}", CONFIG.public_url, create_verification_token(user));
+
+    MAILER.send(&Mail {
+        to      : user.email.clone(),
+        subject : "Verify your email address".to_string(),
+        body    : format!(
+            "Hi {},\r\n\r\nPlease verify your email address by opening the link below.\r\n\r\n{}\r\n\r\nThe link is valid for 48 hours.",
+            user.displayname, link
+        ),
+    })
+}